@@ -1,9 +1,14 @@
 #[macro_use]
 extern crate clap;
-extern crate regex;
+extern crate humantime;
 
 use clap::Shell;
 
+// The command line interface validates the tag expressions.
+#[allow(dead_code)]
+#[path = "src/tags.rs"]
+mod tags;
+
 include!("src/cli.rs");
 
 fn main() {
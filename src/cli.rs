@@ -1,6 +1,6 @@
-use clap::{App, Arg};
-use regex::Regex;
-use std::error::Error;
+use clap::{App, Arg, SubCommand};
+use humantime::parse_duration;
+use tags::TagExpr;
 
 pub fn build_cli() -> App<'static, 'static> {
     App::new("multitest")
@@ -8,6 +8,7 @@ pub fn build_cli() -> App<'static, 'static> {
         .version(crate_version!())
         .arg(
             Arg::with_name("color")
+                .global(true)
                 .long("color")
                 .value_name("WHEN")
                 .possible_values(&["always", "auto", "never"])
@@ -16,19 +17,173 @@ pub fn build_cli() -> App<'static, 'static> {
         )
         .arg(
             Arg::with_name("config_file")
+                .global(true)
                 .long("config")
                 .value_name("CONFIG_FILE")
                 .help("Select a configuration file instead of searching for a multitest.toml file"),
         )
+        .arg(
+            Arg::with_name("dry_run")
+                .global(true)
+                .long("dry-run")
+                .help("Print the command line of the tests instead of running them"),
+        )
+        .arg(
+            Arg::with_name("exact")
+                .global(true)
+                .long("exact")
+                .help("Match the test names of the filters exactly instead of as regexes"),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .global(true)
+                .long("exclude")
+                .value_name("FILTER")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Don't run the tests that match the filter"),
+        )
+        .arg(
+            Arg::with_name("fail_fast")
+                .global(true)
+                .long("fail-fast")
+                .conflicts_with("max_failures")
+                .help("Stop running tests after the first failure"),
+        )
+        .arg(
+            Arg::with_name("failed_first")
+                .global(true)
+                .long("failed-first")
+                .help("Run the tests that failed in the last run before the other tests"),
+        )
         .arg(
             Arg::with_name("filter")
+                .global(true)
                 .long("filter")
                 .value_name("FILTER")
-                .validator(|filter| {
-                    Regex::new(&*filter)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Only run the tests that match one of the filters, a regex or \"path::regex\" to \
+                     also match the configuration file",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclude_tag")
+                .global(true)
+                .long("exclude-tag")
+                .value_name("EXPR")
+                .validator(validate_tag_expr)
+                .help("Don't run the tests whose tags match the expression"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .global(true)
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["human", "json"])
+                .default_value("human")
+                .help("Report the progress as colored text on stderr or JSON events on stdout"),
+        )
+        .arg(
+            Arg::with_name("junit")
+                .global(true)
+                .long("junit")
+                .value_name("PATH")
+                .help("Write a JUnit XML report of the tests"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .global(true)
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .validator(|jobs| match jobs.parse::<usize>() {
+                    Ok(0) => Err("the number of jobs must be at least 1".to_string()),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e.to_string()),
+                })
+                .help("Number of tests to run in parallel [default: number of CPUs]"),
+        )
+        .arg(
+            Arg::with_name("max_failures")
+                .global(true)
+                .long("max-failures")
+                .value_name("N")
+                .validator(|max_failures| match max_failures.parse::<usize>() {
+                    Ok(0) => Err("the maximum number of failures must be at least 1".to_string()),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e.to_string()),
+                })
+                .help("Stop running tests after N failures"),
+        )
+        .arg(
+            Arg::with_name("rerun_failed")
+                .global(true)
+                .long("rerun-failed")
+                .help("Only run the tests that failed in the last run"),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .global(true)
+                .long("retries")
+                .value_name("N")
+                .validator(|retries| {
+                    retries
+                        .parse::<usize>()
                         .map(|_| ())
-                        .map_err(|e| e.description().to_string())
+                        .map_err(|e| e.to_string())
                 })
-                .help("Only run tests that match the filter"),
+                .help("Override how many times the tests that fail are run again"),
+        )
+        .arg(
+            Arg::with_name("show_output")
+                .global(true)
+                .long("show-output")
+                .value_name("WHEN")
+                .possible_values(&["always", "failures", "never"])
+                .default_value("always")
+                .help("When to show the output of the tests"),
+        )
+        .arg(
+            Arg::with_name("tag")
+                .global(true)
+                .long("tag")
+                .value_name("EXPR")
+                .validator(validate_tag_expr)
+                .help(
+                    "Only run the tests whose tags match the expression, e.g. \"slow and not nightly\"",
+                ),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .global(true)
+                .long("timeout")
+                .value_name("DURATION")
+                .validator(validate_duration)
+                .help("Override the timeout of every test, e.g. \"10m\""),
+        )
+        .arg(
+            Arg::with_name("timeout_grace")
+                .global(true)
+                .long("timeout-grace")
+                .value_name("DURATION")
+                .validator(validate_duration)
+                .help(
+                    "Override how long a test that timed out has to exit before being killed",
+                ),
         )
+        .subcommand(
+            SubCommand::with_name("list").about("List the tests without running them"),
+        )
+}
+
+fn validate_duration(duration: String) -> Result<(), String> {
+    parse_duration(&duration)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn validate_tag_expr(expr: String) -> Result<(), String> {
+    TagExpr::parse(&expr).map(|_| ())
 }
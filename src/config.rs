@@ -1,99 +1,435 @@
-use glob::glob;
+use covering::covering_array;
+use error::{ConfigError, ConfigErrorKind, KeyPart, KeyPath, SourceLines};
+use events::{self, Format};
+use expect::{Expectation, MatchKind, Matcher, Stream};
+use filter::Filter;
+use glob::{glob, Pattern};
+use humantime::parse_duration;
+use interrupt::interrupted;
 use liquid::{self, Template};
 use regex::Regex;
-use std::collections::HashMap;
+use runner::{run_hook, run_tests, start_service, stop_service, HookKind, ShowOutput};
+use service::{Readiness, Service, DEFAULT_READY_TIMEOUT};
+use state::State;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
-use test::Test;
-use toml::Value;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tags::TagExpr;
+use template;
+use test::{write_output, ExitCodes, Outcome, Stdin, Test, TestRun, DEFAULT_TIMEOUT_GRACE};
+use toml::{self, Value};
 
 const CONFIG_FILE_NAME: &str = "multitest.toml";
+/// The shell running the string commands and the scripts of the tests by default.
+const DEFAULT_SHELL: [&str; 2] = ["sh", "-c"];
+
+/// The command of a test, as given in the configuration file.
+enum TestCommand {
+    Args(Vec<String>),
+    /// A shell script, from a string `command` or from `script`.
+    Script(String),
+}
+
+impl TestCommand {
+    /// A command is a string, run by a shell, or a non-empty array of strings.
+    fn from_toml(command: &Value, key_path: KeyPath) -> Result<TestCommand, ConfigError> {
+        if let Value::String(ref script) = *command {
+            return Ok(TestCommand::Script(script.clone()));
+        }
+
+        let command: Option<Vec<_>> = command.as_array().and_then(|command| {
+            command
+                .iter()
+                .map(Value::as_str)
+                .map(|arg| arg.map(|s| s.to_string()))
+                .collect()
+        });
+        match command {
+            Some(ref command) if !command.is_empty() => Ok(TestCommand::Args(command.clone())),
+            _ => Err(ConfigError::new(
+                key_path,
+                ConfigErrorKind::InvalidType("a string or a non-empty array of strings"),
+            )),
+        }
+    }
+
+    /// The command line, with `args` added at its end, and the shell running it if it is a
+    /// script or if `use_shell` is true. The script is a template whose outputs are escaped for
+    /// the shell, and each element of `args` or of an array command is a single word of it.
+    fn command_line(
+        self,
+        args: &[String],
+        use_shell: bool,
+        shell: &Option<Vec<String>>,
+    ) -> (Vec<String>, Option<Vec<String>>) {
+        let (script, words) = match self {
+            TestCommand::Args(command) => if use_shell {
+                (String::new(), command.into_iter().chain(args.iter().cloned()).collect())
+            } else {
+                return (command.into_iter().chain(args.iter().cloned()).collect(), None);
+            },
+            TestCommand::Script(script) => (template::shell_escaped(&script), args.to_vec()),
+        };
+
+        let script = words
+            .iter()
+            .enumerate()
+            .map(|(index, word)| template::shell_word(word, index))
+            .fold(script, |script, word| {
+                if script.is_empty() {
+                    word
+                } else {
+                    script + " " + &word
+                }
+            });
+        let shell = match *shell {
+            Some(ref shell) => shell.clone(),
+            None => DEFAULT_SHELL.iter().map(|arg| arg.to_string()).collect(),
+        };
+
+        (vec![script], Some(shell))
+    }
+}
 
 pub struct TestTemplate {
     pub name: Template,
     pub command: Vec<Template>,
+    /// The key path of each argument of the command, or of the script.
+    command_key_paths: Vec<KeyPath>,
+    pub shell: Option<Vec<String>>,
     pub clear_env: bool,
     pub env: Vec<(Template, Template)>,
+    pub timeout: Option<Duration>,
+    pub timeout_grace: Duration,
+    pub expectations: Vec<ExpectationTemplate>,
+    pub exit_codes: ExitCodes,
+    pub tags: Vec<Template>,
+    pub retries: usize,
+    pub retry_delay: Duration,
+    /// The working directory, relative to the configuration file.
+    pub cwd: Option<Template>,
+    pub stdin: Option<StdinTemplate>,
+    pub before: Vec<Template>,
+    pub after: Vec<Template>,
+}
+
+/// An expectation on the output of a test, before its expected value is rendered.
+pub struct ExpectationTemplate {
+    pub stream: Stream,
+    pub kind: MatchKind,
+    pub expected: Template,
+    key_path: KeyPath,
+}
+
+/// The stdin of a test, before its text or the path of its file is rendered.
+pub enum StdinTemplate {
+    Null,
+    Text(Template),
+    File(Template),
 }
 
 impl TestTemplate {
-    fn try_from_test(test: &Test<String, String, String>) -> Result<TestTemplate, ()> {
-        let parser = liquid::ParserBuilder::with_liquid().build();
-        let name_template = match parser.parse(&*test.name) {
-            Ok(name_template) => name_template,
-            Err(error) => {
-                eprintln_red!("error while parsing name template: {}", error);
-                return Err(());
-            }
+    /// `command_key` is the key of the command of the test: `command` or `script`.
+    fn try_from_test(
+        test: &Test<String, String, String>,
+        key_path: &KeyPath,
+        command_key: &str,
+    ) -> Result<TestTemplate, Vec<ConfigError>> {
+        let parser = template::parser();
+        let mut errors = vec![];
+
+        let mut parse = |template: &str, key_path: KeyPath| {
+            parser
+                .parse(template)
+                .map_err(|error| {
+                    errors.push(ConfigError::new(key_path, ConfigErrorKind::Template(error)))
+                })
+                .ok()
         };
 
-        let command_templates = test.command
-            .iter()
-            .map(|arg| {
-                parser.parse(&*arg).map_err(|error| {
-                    eprintln_red!("error while parsing an arg template: {}", error)
-                })
+        let name_template = parse(&test.name, key_path.key("name"));
+
+        let command_key_paths: Vec<_> = (0..test.command.len())
+            .map(|i| match test.shell {
+                Some(_) => key_path.key(command_key),
+                None => key_path.key(command_key).index(i),
             })
-            .collect::<Result<Vec<_>, ()>>()?;
+            .collect();
+        let command_templates: Vec<_> = test.command
+            .iter()
+            .zip(&command_key_paths)
+            .map(|(arg, arg_key_path)| parse(arg, arg_key_path.clone()))
+            .collect();
 
-        let env_templates = test.env
+        let env_templates: Vec<_> = test.env
             .iter()
-            .map(|&(ref name, ref value)| {
-                let name = parser.parse(name).map_err(|error| {
-                    eprintln_red!("error while parsing an arg template: {}", error)
-                })?;
-                let value = parser.parse(value).map_err(|error| {
-                    eprintln_red!("error while parsing an arg template: {}", error)
-                })?;
-
-                Ok((name, value))
+            .enumerate()
+            .map(|(i, (name, value))| {
+                let env_key_path = key_path.key("env").index(i);
+                let name = parse(name, env_key_path.key("name"));
+                let value = parse(value, env_key_path.key("value"));
+
+                match (name, value) {
+                    (Some(name), Some(value)) => Some((name, value)),
+                    _ => None,
+                }
             })
-            .collect::<Result<Vec<_>, ()>>()?;
+            .collect();
+
+        let tag_templates: Vec<_> = test.tags
+            .iter()
+            .enumerate()
+            .map(|(i, tag)| parse(tag, key_path.key("tags").index(i)))
+            .collect();
+
+        let cwd_template = test.cwd
+            .as_ref()
+            .map(|cwd| parse(&cwd.to_string_lossy(), key_path.key("cwd")));
+
+        let before_templates: Vec<_> = test.before
+            .iter()
+            .map(|arg| parse(arg, key_path.key("before")))
+            .collect();
+        let after_templates: Vec<_> = test.after
+            .iter()
+            .map(|arg| parse(arg, key_path.key("after")))
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
 
         Ok(TestTemplate {
-            name: name_template,
-            command: command_templates,
+            name: name_template.unwrap(),
+            command: command_templates.into_iter().map(Option::unwrap).collect(),
+            command_key_paths,
+            shell: test.shell.clone(),
             clear_env: test.clear_env,
-            env: env_templates,
+            env: env_templates.into_iter().map(Option::unwrap).collect(),
+            timeout: test.timeout,
+            timeout_grace: test.timeout_grace,
+            expectations: vec![],
+            exit_codes: test.exit_codes.clone(),
+            tags: tag_templates.into_iter().map(Option::unwrap).collect(),
+            retries: test.retries,
+            retry_delay: test.retry_delay,
+            cwd: cwd_template.map(Option::unwrap),
+            stdin: None,
+            before: before_templates.into_iter().map(Option::unwrap).collect(),
+            after: after_templates.into_iter().map(Option::unwrap).collect(),
         })
     }
 }
 
+pub struct RunOptions {
+    /// Only run the tests that match one of these filters, if there are any.
+    pub filters: Vec<Filter>,
+    /// Don't run the tests that match one of these filters.
+    pub excludes: Vec<Filter>,
+    /// Only run the tests whose tags match this expression.
+    pub tag: Option<TagExpr>,
+    /// Don't run the tests whose tags match this expression.
+    pub exclude_tag: Option<TagExpr>,
+    /// Only run the tests that failed the last time they were run.
+    pub rerun_failed: bool,
+    /// Run the tests that failed the last time they were run before the other tests.
+    pub failed_first: bool,
+    /// The outcomes of the last run of the tests.
+    pub last_run: State,
+    /// Stop starting tests once this number of tests failed.
+    pub max_failures: Option<usize>,
+    /// Override the number of retries of every test.
+    pub retries: Option<usize>,
+    /// Whether the tests are run by a continuous integration service. Their stdin is then null by
+    /// default instead of being inherited.
+    pub ci: bool,
+    pub jobs: usize,
+    /// Override the timeout of every test.
+    pub timeout: Option<Duration>,
+    /// Override the timeout grace period of every test.
+    pub timeout_grace: Option<Duration>,
+    pub show_output: ShowOutput,
+    /// Capture the output of every test, even if it is not shown.
+    pub capture_output: bool,
+    pub format: Format,
+}
+
+impl RunOptions {
+    /// Whether a test matches the filters and the tag expressions, and failed the last time it
+    /// was run with `rerun_failed`.
+    fn selects(&self, test: &Test<String, String, String>) -> bool {
+        let matches = |filter: &Filter| filter.matches(test);
+
+        let matches_filter = self.filters.is_empty() || self.filters.iter().any(&matches);
+        let excluded = self.excludes.iter().any(&matches);
+        let matches_tag = match self.tag {
+            Some(ref tag) => tag.matches(&test.tags),
+            None => true,
+        };
+        let excluded_tag = match self.exclude_tag {
+            Some(ref exclude_tag) => exclude_tag.matches(&test.tags),
+            None => false,
+        };
+
+        let matches_last_run = !self.rerun_failed || self.last_run.failed(test);
+
+        matches_filter && !excluded && matches_tag && !excluded_tag && matches_last_run
+    }
+}
+
+/// The result of a test that was run.
+pub struct TestResult {
+    pub name: String,
+    pub config_file: PathBuf,
+    pub outcome: Outcome,
+    pub duration: Duration,
+    pub exit_code: Option<i32>,
+    /// The number of times the test was run.
+    pub attempts: usize,
+    /// Why the test didn't succeed.
+    pub failure_message: Option<String>,
+    /// The output of the test, if it was captured.
+    pub output: Vec<u8>,
+    /// The name and the log of the services used by the test, if it failed.
+    pub service_logs: Vec<(String, Vec<u8>)>,
+}
+
+impl TestResult {
+    fn new(test: &Test<String, String, String>, test_run: TestRun) -> TestResult {
+        TestResult {
+            outcome: test_run.outcome(),
+            duration: test_run.duration,
+            exit_code: test_run.exit_code(),
+            attempts: test_run.attempts,
+            failure_message: test_run.failure_message(),
+            output: test_run.output,
+            config_file: test.config_file.clone().unwrap_or_default(),
+            name: test.name.clone(),
+            service_logs: vec![],
+        }
+    }
+
+    /// Write the output of the test to stdout, followed by the logs of the services it used.
+    fn write_output(&self) {
+        write_output(&self.output);
+        for (name, log) in &self.service_logs {
+            eprintln_bold!("Log of service {}:", name);
+            write_output(log);
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct RunConfigResult {
-    ignored: u32,
-    successes: Vec<String>,
-    failures: Vec<String>,
+    /// The configuration file and the name of the tests that didn't match the filter.
+    pub ignored: Vec<(PathBuf, String)>,
+    pub results: Vec<TestResult>,
+    /// The configuration file and the name of the tests that were not run because too many tests
+    /// failed, because a setup command failed or because Ctrl-C was pressed.
+    pub not_run: Vec<(PathBuf, String)>,
+    /// The setup and teardown commands and the services that failed.
+    pub hook_failures: Vec<TestResult>,
+    /// Whether Ctrl-C was pressed during the run.
+    pub interrupted: bool,
+    /// Whether only the tests that failed in the last run were selected.
+    pub rerun_failed: bool,
 }
 
 impl RunConfigResult {
-    pub fn merge(&mut self, other: RunConfigResult) {
-        self.ignored += other.ignored;
-        self.successes.extend(other.successes);
-        self.failures.extend(other.failures);
+    fn with_outcome(&self, outcome: Outcome) -> Vec<&TestResult> {
+        self.results
+            .iter()
+            .filter(|result| result.outcome == outcome)
+            .collect()
     }
 
-    pub fn summary(&self) {
-        let ignored = self.ignored;
-        let successes = &self.successes;
-        let failures = &self.failures;
+    pub fn summary(&self, show_output: ShowOutput) {
+        let ignored = self.ignored.len();
+        let successes = self.with_outcome(Outcome::Success);
+        let flaky = self.with_outcome(Outcome::Flaky);
+        let failures = self.with_outcome(Outcome::Failure);
+        let timed_out = self.with_outcome(Outcome::TimedOut);
+        let skipped = self.with_outcome(Outcome::Skipped);
+        let errors = self.with_outcome(Outcome::Error);
 
-        let total = successes.len() + failures.len();
+        let total = self.results.len();
+
+        // The output of the tests that didn't succeed is shown here instead of when they finish.
+        let show_output = show_output == ShowOutput::Failures;
 
         if !successes.is_empty() {
             eprintln_green!("Successes ({}/{}):", successes.len(), total);
             for success in successes {
-                eprintln_green!("  {}", success);
+                eprintln_green!("  {}", success.name);
+            }
+        }
+
+        if !flaky.is_empty() {
+            eprintln_bold!("Flaky ({}/{}):", flaky.len(), total);
+            for test in flaky {
+                eprintln_bold!("  {} ({} attempts)", test.name, test.attempts);
             }
         }
 
         if !failures.is_empty() {
             eprintln_red!("Failures ({}/{}):", failures.len(), total);
             for failure in failures {
-                eprintln_red!("  {}", failure);
+                eprintln_red!("  {}", failure.name);
+                if show_output {
+                    failure.write_output();
+                }
+            }
+        }
+
+        if !timed_out.is_empty() {
+            eprintln_red!("Timed out ({}/{}):", timed_out.len(), total);
+            for test in timed_out {
+                eprintln_red!("  {}", test.name);
+                if show_output {
+                    test.write_output();
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            eprintln_red!("Errors ({}/{}):", errors.len(), total);
+            for test in errors {
+                eprintln_red!("  {}", test.name);
+                if show_output {
+                    test.write_output();
+                }
+            }
+        }
+
+        if !skipped.is_empty() {
+            eprintln_bold!("Skipped ({}/{}):", skipped.len(), total);
+            for test in skipped {
+                eprintln_bold!("  {}", test.name);
+            }
+        }
+
+        if !self.hook_failures.is_empty() {
+            eprintln_red!(
+                "Setup, teardown and service failures ({}):",
+                self.hook_failures.len()
+            );
+            for hook in &self.hook_failures {
+                eprintln_red!("  {} ({})", hook.name, hook.config_file.display());
+                if show_output {
+                    write_output(&hook.output);
+                }
+            }
+        }
+
+        if !self.not_run.is_empty() {
+            eprintln_bold!("Not run ({}):", self.not_run.len());
+            for (_, name) in &self.not_run {
+                eprintln_bold!("  {}", name);
             }
         }
 
@@ -101,15 +437,31 @@ impl RunConfigResult {
             eprintln_bold!("{} tests ignored", ignored);
         }
 
-        if total == 0 {
+        if self.interrupted {
+            eprintln_red!("Interrupted")
+        } else if self.nothing_to_rerun() {
+            eprintln_green!("No failed tests to rerun")
+        } else if total == 0 {
             eprintln_red!("No tests executed")
         }
     }
 
+    /// Whether the tests to rerun were selected and none of them failed in the last run.
+    fn nothing_to_rerun(&self) -> bool {
+        self.rerun_failed && self.results.is_empty() && self.not_run.is_empty()
+    }
+
+    /// Whether no test, setup or teardown command failed, and the run was not interrupted. The
+    /// skipped tests don't count as failures, and no tests must be run only when there are no
+    /// failed tests to rerun.
     pub fn is_success(&self) -> bool {
-        let total = self.successes.len() + self.failures.len();
+        let total = self.results.len();
+        let tests_succeeded = self.results
+            .iter()
+            .all(|result| !result.outcome.is_failure())
+            && (total > 0 || self.nothing_to_rerun());
 
-        self.failures.is_empty() && total > 0
+        tests_succeeded && self.hook_failures.is_empty() && !self.interrupted
     }
 }
 
@@ -120,377 +472,2404 @@ struct Variable {
 }
 
 impl Variable {
-    fn try_from_tuple((key, value): (&String, &Value)) -> Result<Variable, ()> {
-        let name = key.clone();
-
-        match value.as_array() {
-            Some(values) => {
-                let values = values.iter().map(toml_value_to_liquid).collect();
-
-                Ok(Variable { name, values })
-            }
-            None => {
-                eprintln_red!("The values of the variables must be arrays");
-                Err(())
+    /// Parse a variable. Its values are either an array, or are generated from a table with one
+    /// of the keys:
+    ///
+    /// - `command`: the lines of the output of a command, run from the directory of the
+    ///   configuration file,
+    /// - `glob`: the paths matching a glob pattern, relative to this directory,
+    /// - `file`: the lines of a file.
+    fn from_toml(
+        key: &str,
+        value: &Value,
+        key_path: &KeyPath,
+        config_dir: &Path,
+    ) -> Result<Variable, ConfigError> {
+        let name = key.to_string();
+        let key_path = key_path.key(key);
+
+        let invalid_type = |key_path, expected| {
+            ConfigError::new(key_path, ConfigErrorKind::InvalidType(expected))
+        };
+        let expected_variable =
+            "an array or a table with one of the keys `command`, `glob` or `file`";
+
+        let values = match *value {
+            Value::Array(ref values) => values.iter().map(toml_value_to_liquid).collect(),
+            Value::Table(ref table) if table.len() == 1 => {
+                let (source, value) = table.iter().next().unwrap();
+                let key_path = key_path.key(&**source);
+
+                match (&**source, value) {
+                    ("command", _) => {
+                        let command: Option<Vec<_>> = value
+                            .as_array()
+                            .and_then(|command| command.iter().map(Value::as_str).collect());
+                        match command {
+                            Some(ref command) if !command.is_empty() => {
+                                lines_to_liquid(&command_output(command, config_dir, &key_path)?)
+                            }
+                            _ => return Err(invalid_type(key_path, "a non-empty array of strings")),
+                        }
+                    }
+                    ("glob", Value::String(pattern)) => glob_paths(pattern, config_dir)
+                        .map_err(|kind| ConfigError::new(key_path, kind))?
+                        .into_iter()
+                        .map(liquid::Value::scalar)
+                        .collect(),
+                    ("file", Value::String(file)) => {
+                        let mut text = String::new();
+                        File::open(config_dir.join(file))
+                            .and_then(|mut file| file.read_to_string(&mut text))
+                            .map_err(|error| ConfigError::new(key_path, ConfigErrorKind::Io(error)))?;
+                        lines_to_liquid(&text)
+                    }
+                    ("glob", _) | ("file", _) => return Err(invalid_type(key_path, "a string")),
+                    _ => return Err(invalid_type(key_path, expected_variable)),
+                }
             }
-        }
+            _ => return Err(invalid_type(key_path, expected_variable)),
+        };
+
+        Ok(Variable { name, values })
     }
 }
 
-struct ParseResult {
-    tests: Vec<Test<String, String, String>>,
-    includes: Vec<PathBuf>,
+/// The non-empty lines of a text, without their leading and trailing whitespaces.
+fn lines_to_liquid(text: &str) -> Vec<liquid::Value> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(liquid::Value::scalar)
+        .collect()
 }
 
-fn toml_value_to_liquid(toml_value: &Value) -> liquid::Value {
-    match *toml_value {
-        Value::String(ref value) => liquid::Value::scalar(value.clone()),
-        Value::Integer(value) => liquid::Value::scalar(value as f32),
-        Value::Float(value) => liquid::Value::scalar(value as f32),
-        Value::Boolean(value) => liquid::Value::scalar(value),
-        Value::Datetime(ref value) => liquid::Value::scalar(value.to_string()),
-        Value::Array(ref value) => {
-            liquid::Value::Array(value.iter().map(toml_value_to_liquid).collect())
-        }
-        Value::Table(ref value) => liquid::Value::Object(
-            value
-                .iter()
-                .map(|(key, value)| (key.clone(), toml_value_to_liquid(value)))
-                .collect(),
-        ),
+/// Run the command generating the values of a variable and return its output.
+fn command_output(
+    command: &[&str],
+    config_dir: &Path,
+    key_path: &KeyPath,
+) -> Result<String, ConfigError> {
+    let output = Command::new(command[0])
+        .args(&command[1..])
+        .current_dir(config_dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|error| ConfigError::new(key_path.clone(), ConfigErrorKind::Command(error)))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(ConfigError::new(
+            key_path.clone(),
+            ConfigErrorKind::CommandFailed(output.status),
+        ))
     }
 }
 
-pub fn find_config_file() -> Option<PathBuf> {
-    let current_dir = env::current_dir().unwrap();
-    let mut current = &*current_dir;
-
-    loop {
-        let config_file = current.join(CONFIG_FILE_NAME);
-        if config_file.metadata().is_ok() {
-            return Some(config_file.to_path_buf());
-        }
+/// The paths matching a glob pattern, relative to the directory of the configuration file, in
+/// alphabetical order.
+fn glob_paths(pattern: &str, config_dir: &Path) -> Result<Vec<String>, ConfigErrorKind> {
+    let mut paths = vec![];
 
-        match current.parent() {
-            Some(parent) => {
-                current = parent;
-            }
-            None => {
-                break;
-            }
-        }
+    for path in glob(&include_pattern(config_dir, pattern)).map_err(ConfigErrorKind::InvalidGlob)? {
+        let path = path.map_err(ConfigErrorKind::Glob)?;
+        let path = if Path::new(pattern).is_absolute() {
+            &*path
+        } else {
+            path.strip_prefix(config_dir).unwrap_or(&path)
+        };
+        paths.push(path.to_string_lossy().into_owned());
     }
 
-    None
+    Ok(paths)
 }
 
-fn test_from_toml(test: &Value) -> Result<Test<String, String, String>, ()> {
-    let name = match test.get("name").and_then(Value::as_str) {
-        Some(name) => name,
-        None => {
-            eprintln_red!("Error: test without a name");
-            return Err(());
-        }
-    };
-
-    let command = match test.get("command").and_then(Value::as_array) {
-        Some(command) => {
-            let command: Option<Vec<_>> = command
-                .iter()
-                .map(Value::as_str)
-                .map(|arg| arg.map(|s| s.to_string()))
-                .collect();
-            match command {
-                Some(command) => command,
-                None => {
-                    eprintln_red!("Error: invalid command for \"{}\"", name);
-                    return Err(());
-                }
-            }
-        }
-        None => {
-            eprintln_red!("Error: test without command");
-            return Err(());
-        }
-    };
-
-    let clear_env = test.get("clear_env")
-        .and_then(Value::as_bool)
-        .unwrap_or(false);
-
-    let env = match test.get("env").and_then(Value::as_array) {
-        Some(env) => {
-            let env: Result<Vec<_>, ()> = env.iter().map(env_from_table).collect();
-            env?
-        }
-        None => vec![],
-    };
-
-    Ok(Test::new(name, command, clear_env, env))
+/// A group of variables whose values vary together. Each variable of `variables` is its own
+/// group, while the variables of a zip are in the same group.
+#[derive(Debug)]
+struct Dimension {
+    names: Vec<String>,
+    /// The values of the variables of the group, for each step.
+    values: Vec<liquid::Object>,
 }
 
-fn env_from_table(table: &Value) -> Result<(String, String), ()> {
-    let name = table.get("name").and_then(Value::as_str);
-    let value = table.get("value").and_then(Value::as_str);
+impl Dimension {
+    fn from_variable(variable: Variable) -> Dimension {
+        let Variable { name, values } = variable;
+        let values = values
+            .into_iter()
+            .map(|value| {
+                let mut values = HashMap::new();
+                values.insert(name.clone(), value);
+                values
+            })
+            .collect();
 
-    match (name, value) {
-        (Some(name), Some(value)) => Ok((name.to_string(), value.to_string())),
-        (Some(name), None) => {
-            eprintln_red!("Error: environment variable \"{}\" without a value", name);
-            Err(())
-        }
-        (None, Some(value)) => {
-            eprintln_red!(
-                "Error: environment variable with value \"{}\" without a name",
-                value
-            );
-            Err(())
-        }
-        (None, None) => {
-            eprintln_red!("Error: environment variable with neither a name or a value");
-            Err(())
+        Dimension {
+            names: vec![name],
+            values,
         }
     }
-}
 
-fn gen_matrices(
-    test_template: &TestTemplate,
-    variables: &[Variable],
-    variables_values: &mut HashMap<String, liquid::Value>,
-    collected_test: &mut Vec<Test<String, String, String>>,
-) -> Result<(), ()> {
-    if variables.is_empty() {
-        let name = match test_template.name.render(variables_values) {
-            Ok(name) => name,
-            Err(error) => {
-                eprintln_red!("error while rendering name template: {}", error);
-                return Err(());
+    /// Parse a zip, a table of variables that must have the same number of values.
+    fn zip_from_toml(
+        zip: &Value,
+        key_path: &KeyPath,
+        config_dir: &Path,
+    ) -> Result<Dimension, Vec<ConfigError>> {
+        let table = match zip.as_table() {
+            Some(table) => table,
+            None => {
+                return Err(vec![ConfigError::new(
+                    key_path.clone(),
+                    ConfigErrorKind::InvalidType("a table"),
+                )])
             }
         };
 
-        variables_values.insert("name".to_string(), liquid::Value::scalar(name.clone()));
-
-        let command = test_template
-            .command
+        let mut errors = vec![];
+        let variables: Vec<_> = table
             .iter()
-            .map(|arg_template| match arg_template.render(variables_values) {
-                Ok(name) => Ok(name),
-                Err(error) => {
-                    eprintln_red!("error while rendering a name template: {}", error);
-                    Err(())
-                }
+            .filter_map(|(key, value)| {
+                Variable::from_toml(key, value, key_path, config_dir)
+                    .map_err(|error| errors.push(error))
+                    .ok()
             })
-            .collect::<Result<Vec<_>, ()>>()?;
+            .collect();
+
+        if let Some(first) = variables.first() {
+            for variable in &variables[1..] {
+                if variable.values.len() != first.values.len() {
+                    errors.push(ConfigError::new(
+                        key_path.key(&*variable.name),
+                        ConfigErrorKind::ZipLength(first.name.clone(), first.values.len()),
+                    ));
+                }
+            }
+        }
 
-        let env = test_template
-            .env
-            .iter()
-            .map(|&(ref name_template, ref value_template)| {
-                let name = match name_template.render(variables_values) {
-                    Ok(name) => Ok(name),
-                    Err(error) => {
-                        eprintln_red!(
-                            "error while rendering an environment variable name \
-                             template: {}",
-                            error
-                        );
-                        Err(())
-                    }
-                }?;
-
-                let value = match value_template.render(variables_values) {
-                    Ok(name) => Ok(name),
-                    Err(error) => {
-                        eprintln_red!(
-                            "error while rendering an environment variable value \
-                             template: {}",
-                            error
-                        );
-                        Err(())
-                    }
-                }?;
+        if !errors.is_empty() {
+            return Err(errors);
+        }
 
-                Ok((name, value))
+        let length = variables.first().map_or(0, |variable| variable.values.len());
+        let values = (0..length)
+            .map(|i| {
+                variables
+                    .iter()
+                    .map(|variable| (variable.name.clone(), variable.values[i].clone()))
+                    .collect()
             })
-            .collect::<Result<Vec<_>, ()>>()?;
+            .collect();
 
-        collected_test.push(Test::new(name, command, test_template.clear_env, env));
+        Ok(Dimension {
+            names: variables.into_iter().map(|variable| variable.name).collect(),
+            values,
+        })
+    }
+}
 
-        Ok(())
-    } else {
-        let current_variable = &variables[0];
-        let current_variable_name = &current_variable.name;
-        for value in &current_variable.values {
-            variables_values.insert(current_variable_name.to_string(), value.clone());
-            gen_matrices(
-                test_template,
-                &variables[1..],
-                variables_values,
-                collected_test,
-            )?;
-        }
+/// How the combinations of the values of the variables are generated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MatrixStrategy {
+    /// Every combination: the Cartesian product of the variables.
+    Full,
+    /// Enough combinations so that every combination of the values of any N variables is used
+    /// at least once. Pairwise is 2-wise.
+    NWise(usize),
+}
 
-        Ok(())
+impl MatrixStrategy {
+    fn from_toml(test: &Value, key_path: &KeyPath) -> Result<MatrixStrategy, ConfigError> {
+        let strategy = match test.get("matrix_strategy") {
+            Some(strategy) => strategy.as_str(),
+            None => return Ok(MatrixStrategy::Full),
+        };
+
+        let n_wise = |strategy: &str| {
+            let mut parts = strategy.splitn(2, '-');
+            match (parts.next(), parts.next()) {
+                (Some(n), Some("wise")) => n.parse().ok().filter(|&n| n > 0),
+                _ => None,
+            }
+        };
+
+        match strategy {
+            Some("full") => Ok(MatrixStrategy::Full),
+            Some("pairwise") => Ok(MatrixStrategy::NWise(2)),
+            Some(strategy) if n_wise(strategy).is_some() => {
+                Ok(MatrixStrategy::NWise(n_wise(strategy).unwrap()))
+            }
+            _ => Err(ConfigError::new(
+                key_path.key("matrix_strategy"),
+                ConfigErrorKind::InvalidType("\"full\", \"pairwise\" or like \"3-wise\""),
+            )),
+        }
     }
 }
 
-fn parse_config(config_filename: &Path) -> Result<ParseResult, ()> {
-    let mut config_file = match File::open(&*config_filename) {
-        Ok(file) => file,
-        Err(error) => {
-            eprintln_red!("Cannot open {}: {}", config_filename.display(), error);
-            return Err(());
-        }
-    };
+/// The variables of a test, and the rules changing the combinations of their values.
+struct Matrix {
+    /// The variables of the file, part of every combination unless the test overrides them.
+    file_variables: liquid::Object,
+    dimensions: Vec<Dimension>,
+    strategy: MatrixStrategy,
+    /// The seed used to generate the combinations of the n-wise strategy.
+    seed: u64,
+    /// Partial assignments of the variables. The combinations matching one of them are dropped.
+    exclude: Vec<liquid::Object>,
+    /// Extra combinations, that can have other variables.
+    include: Vec<liquid::Object>,
+}
 
-    let mut config_text = String::new();
+impl Matrix {
+    /// Parse the `variables`, `zip` and `matrix` keys of a test.
+    fn from_toml(
+        test: &Value,
+        key_path: &KeyPath,
+        config_dir: &Path,
+        file_variables: &liquid::Object,
+    ) -> Result<Matrix, Vec<ConfigError>> {
+        let mut errors = vec![];
+
+        let variables_key_path = key_path.key("variables");
+        let mut dimensions: Vec<_> = match test.get("variables") {
+            Some(variables) => match variables.as_table() {
+                Some(table) => table
+                    .iter()
+                    .filter_map(|(key, value)| {
+                        Variable::from_toml(key, value, &variables_key_path, config_dir)
+                            .map_err(|error| errors.push(error))
+                            .ok()
+                    })
+                    .map(Dimension::from_variable)
+                    .collect(),
+                None => {
+                    errors.push(ConfigError::new(
+                        variables_key_path,
+                        ConfigErrorKind::InvalidType("a table"),
+                    ));
+                    vec![]
+                }
+            },
+            None => vec![],
+        };
 
-    if let Err(error) = config_file.read_to_string(&mut config_text) {
-        eprintln_red!("Error while reading {}: {}", CONFIG_FILE_NAME, error);
-        return Err(());
-    }
+        // There can be a single zip, or an array of independent zips.
+        let zip_key_path = key_path.key("zip");
+        let zips = match test.get("zip") {
+            Some(Value::Array(zips)) => zips
+                .iter()
+                .enumerate()
+                .map(|(i, zip)| (zip, zip_key_path.index(i)))
+                .collect(),
+            Some(zip) => vec![(zip, zip_key_path)],
+            None => vec![],
+        };
 
-    let config_parsed = match config_text.parse::<Value>() {
-        Ok(config) => config,
-        Err(error) => {
-            eprintln_red!("Error while parsing {}: {}", CONFIG_FILE_NAME, error);
-            return Err(());
+        for (zip, zip_key_path) in zips {
+            if let Some(dimension) = collect_errors(
+                Dimension::zip_from_toml(zip, &zip_key_path, config_dir),
+                &mut errors,
+            ) {
+                for name in &dimension.names {
+                    if dimensions.iter().any(|other| other.names.contains(name)) {
+                        errors.push(ConfigError::new(
+                            zip_key_path.key(&**name),
+                            ConfigErrorKind::DuplicateVariable,
+                        ));
+                    }
+                }
+                dimensions.push(dimension);
+            }
         }
-    };
 
-    let mut collected_tests = vec![];
+        let matrix_key_path = key_path.key("matrix");
+        let (exclude, include) = match test.get("matrix") {
+            Some(matrix) if matrix.is_table() => (
+                combinations_from_toml(matrix, &matrix_key_path, "exclude", &mut errors),
+                combinations_from_toml(matrix, &matrix_key_path, "include", &mut errors),
+            ),
+            Some(_) => {
+                errors.push(ConfigError::new(
+                    matrix_key_path.clone(),
+                    ConfigErrorKind::InvalidType("a table"),
+                ));
+                (vec![], vec![])
+            }
+            None => (vec![], vec![]),
+        };
 
-    if let Some(tests) = config_parsed.get("tests").and_then(Value::as_array) {
-        for test in tests {
-            let test_template = TestTemplate::try_from_test(&test_from_toml(test)?)?;
+        let strategy = MatrixStrategy::from_toml(test, key_path)
+            .map_err(|error| errors.push(error))
+            .unwrap_or(MatrixStrategy::Full);
+
+        let seed = match test.get("matrix_seed") {
+            Some(seed) => match seed.as_integer() {
+                Some(seed) if seed >= 0 => seed as u64,
+                _ => {
+                    errors.push(ConfigError::new(
+                        key_path.key("matrix_seed"),
+                        ConfigErrorKind::InvalidType("a positive integer"),
+                    ));
+                    0
+                }
+            },
+            None => 0,
+        };
 
-            let variables = match test.get("variables").and_then(Value::as_table) {
-                Some(table) => {
-                    let variables: Result<Vec<_>, ()> =
-                        table.into_iter().map(Variable::try_from_tuple).collect();
-                    variables?
+        for (i, exclude) in exclude.iter().enumerate() {
+            for name in exclude.keys() {
+                if !dimensions
+                    .iter()
+                    .any(|dimension| dimension.names.contains(name))
+                    && !file_variables.contains_key(name)
+                {
+                    errors.push(ConfigError::new(
+                        matrix_key_path.key("exclude").index(i).key(&**name),
+                        ConfigErrorKind::UnknownVariable,
+                    ));
                 }
-                None => vec![],
-            };
+            }
+        }
 
-            gen_matrices(
-                &test_template,
-                &variables[..],
-                &mut HashMap::new(),
-                &mut collected_tests,
-            )?;
+        if errors.is_empty() {
+            Ok(Matrix {
+                file_variables: file_variables.clone(),
+                dimensions,
+                strategy,
+                seed,
+                exclude,
+                include,
+            })
+        } else {
+            Err(errors)
         }
     }
 
-    let mut collected_includes = vec![];
-
-    if let Some(includes) = config_parsed.get("includes").and_then(Value::as_array) {
-        for include in includes {
-            match Value::as_str(include) {
-                Some(include) => match glob(include) {
-                    Ok(paths) => for path in paths {
-                        match path {
-                            Ok(path) => collected_includes.push(path),
-                            Err(err) => {
-                                eprintln_red!("{}", err);
-                                return Err(());
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        eprintln_red!("Invalid include glob pattern: {}", err);
-                        return Err(());
+    /// The combinations of the values of the variables, generated according to the strategy,
+    /// without the excluded combinations, followed by the included ones.
+    fn combinations(&self) -> Vec<liquid::Object> {
+        let mut combinations = vec![];
+
+        match self.strategy {
+            MatrixStrategy::Full => cartesian_product(
+                &self.dimensions,
+                &mut self.file_variables.clone(),
+                &mut combinations,
+            ),
+            MatrixStrategy::NWise(strength) => {
+                let sizes: Vec<_> = self.dimensions
+                    .iter()
+                    .map(|dimension| dimension.values.len())
+                    .collect();
+
+                for row in covering_array(&sizes, strength, self.seed) {
+                    let mut combination = self.file_variables.clone();
+                    for (dimension, &level) in self.dimensions.iter().zip(&row) {
+                        combination.extend(dimension.values[level].clone());
                     }
-                },
-                None => {
-                    eprintln_red!("includes must be strings");
-                    return Err(());
+                    combinations.push(combination);
                 }
             }
         }
-    }
 
-    Ok(ParseResult {
-        tests: collected_tests,
-        includes: collected_includes,
-    })
+        combinations.retain(|combination| {
+            !self.exclude.iter().any(|exclude| {
+                exclude
+                    .iter()
+                    .all(|(name, value)| combination.get(name) == Some(value))
+            })
+        });
+        combinations.extend(self.include.iter().map(|include| {
+            let mut combination = self.file_variables.clone();
+            combination.extend(include.clone());
+            combination
+        }));
+
+        combinations
+    }
 }
 
-pub fn run_config(config_filename: &Path, filter: &Option<Regex>) -> Result<RunConfigResult, ()> {
-    let mut result: RunConfigResult = Default::default();
-    let current_dir = match env::current_dir() {
-        Ok(current_dir) => current_dir,
-        Err(err) => {
-            eprintln_red!("cannot get the current working directory: {}", err);
-            return Err(());
-        }
+/// Parse an array of tables giving values to variables, like `matrix.exclude`.
+fn combinations_from_toml(
+    matrix: &Value,
+    key_path: &KeyPath,
+    key: &str,
+    errors: &mut Vec<ConfigError>,
+) -> Vec<liquid::Object> {
+    let tables = match matrix.get(key) {
+        Some(tables) => tables.as_array(),
+        None => return vec![],
     };
 
-    let ParseResult { tests, includes } = parse_config(config_filename)?;
-
-    // We move to the directory containing the configuration file. This way tests are always
-    // executed from this directory.
-    let config_dir = config_filename.parent().unwrap();
-
-    if config_dir.to_str() != Some("") {
-        if let Err(error) = env::set_current_dir(config_dir) {
-            eprintln_red!(
-                "Cannot move the directory containing {}: {}",
-                config_filename.display(),
-                error
-            );
-            return Err(());
+    let tables = match tables {
+        Some(tables) if tables.iter().all(Value::is_table) => tables,
+        _ => {
+            errors.push(ConfigError::new(
+                key_path.key(key),
+                ConfigErrorKind::InvalidType("an array of tables"),
+            ));
+            return vec![];
         }
-    }
+    };
 
-    for include in &includes {
-        eprintln_bold!("Including {}", include.display());
-        result.merge(run_config(include, filter)?);
-    }
+    tables
+        .iter()
+        .filter_map(Value::as_table)
+        .map(|table| {
+            table
+                .iter()
+                .map(|(name, value)| (name.clone(), toml_value_to_liquid(value)))
+                .collect()
+        })
+        .collect()
+}
 
-    if !includes.is_empty() {
-        eprintln_bold!("Going back to {}", config_filename.display());
-    }
+/// The values used for the tests of a configuration file that don't specify them.
+struct FileDefaults {
+    /// The `[defaults]` table: its keys are used by the tests that don't have them.
+    keys: toml::value::Table,
+    /// The `[variables]` of the file, available in the templates of every test.
+    variables: liquid::Object,
+    /// The `[[env]]` of the file, set for every test that doesn't set the same variables.
+    env: Vec<(String, String)>,
+    /// The `[templates.<name>]` tables, that the tests and the other templates can extend.
+    templates: BTreeMap<String, toml::value::Table>,
+}
 
-    for test in tests {
-        if let Some(ref regex) = *filter {
-            if !regex.is_match(&*test.name) {
-                result.ignored += 1;
-                eprintln_bold!("Test {} ignored", test.name);
-                continue;
+impl FileDefaults {
+    /// Parse the defaults of a configuration file. What is valid is still returned if there are
+    /// errors, so that the errors of the tests can be reported too.
+    fn from_toml(config: &Value, errors: &mut Vec<ConfigError>) -> FileDefaults {
+        let root = KeyPath::root();
+
+        let mut table = |key: &str| match config.get(key) {
+            Some(Value::Table(table)) => table.clone(),
+            Some(_) => {
+                errors.push(ConfigError::new(
+                    root.key(key),
+                    ConfigErrorKind::InvalidType("a table"),
+                ));
+                Default::default()
             }
-        }
+            None => Default::default(),
+        };
 
-        let test_success = test.run();
-        if test_success {
-            result.successes.push(test.name);
-        } else {
-            result.failures.push(test.name);
-        }
+        let keys = table("defaults");
+        let templates_table = table("templates");
+        // Unlike the variables of a test, an array is not a list of values, so it is rejected.
+        let variables = table("variables")
+            .iter()
+            .filter_map(|(name, value)| match *value {
+                Value::Array(_) | Value::Table(_) => {
+                    errors.push(ConfigError::new(
+                        root.key("variables").key(&**name),
+                        ConfigErrorKind::InvalidType("a string, a number or a boolean"),
+                    ));
+                    None
+                }
+                _ => Some((name.clone(), toml_value_to_liquid(value))),
+            })
+            .collect();
+
+        let parser = template::parser();
+        let env = match config.get("env") {
+            Some(Value::Array(env)) => env.iter()
+                .enumerate()
+                .filter_map(|(i, table)| {
+                    let key_path = root.key("env").index(i);
+                    let (name, value) = collect_errors(env_from_table(table, &key_path), errors)?;
+
+                    // The templates are checked here, so that their errors are only reported
+                    // once and not for every test.
+                    let mut is_valid = true;
+                    for &(template, key) in &[(&name, "name"), (&value, "value")] {
+                        if let Err(error) = parser.parse(template) {
+                            errors.push(ConfigError::new(
+                                key_path.key(key),
+                                ConfigErrorKind::Template(error),
+                            ));
+                            is_valid = false;
+                        }
+                    }
+
+                    if is_valid {
+                        Some((name, value))
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+            Some(_) => {
+                errors.push(ConfigError::new(
+                    root.key("env"),
+                    ConfigErrorKind::InvalidType("an array of tables"),
+                ));
+                vec![]
+            }
+            None => vec![],
+        };
+
+        let templates_key_path = root.key("templates");
+        let templates = templates_table
+            .into_iter()
+            .map(|(name, template)| match template {
+                Value::Table(template) => (name, template),
+                _ => {
+                    errors.push(ConfigError::new(
+                        templates_key_path.key(&*name),
+                        ConfigErrorKind::InvalidType("a table"),
+                    ));
+                    (name, Default::default())
+                }
+            })
+            .collect();
+
+        let defaults = FileDefaults {
+            keys,
+            variables,
+            env,
+            templates,
+        };
+
+        // The templates are checked even if no test extends them.
+        for (name, template) in &defaults.templates {
+            let key_path = templates_key_path.key(&**name);
+            if let Err(error) = defaults.template_chain(template, &key_path, vec![name.clone()]) {
+                push_error(errors, error);
+            }
+        }
+
+        defaults
+    }
+
+    /// The names of the templates extended by a test or a template, from the closest one.
+    /// `chain` contains the name of the template, to detect the cycles.
+    fn template_chain(
+        &self,
+        table: &toml::value::Table,
+        key_path: &KeyPath,
+        mut chain: Vec<String>,
+    ) -> Result<Vec<String>, ConfigError> {
+        let start = chain.len();
+        let mut table = table;
+        let mut key_path = key_path.clone();
+
+        loop {
+            let extends_key_path = key_path.key("extends");
+            let name = match table.get("extends") {
+                Some(Value::String(name)) => name,
+                Some(_) => {
+                    return Err(ConfigError::new(
+                        extends_key_path,
+                        ConfigErrorKind::InvalidType("the name of a template"),
+                    ))
+                }
+                None => return Ok(chain.split_off(start)),
+            };
+
+            if chain.contains(name) {
+                chain.push(name.clone());
+                return Err(ConfigError::new(
+                    extends_key_path,
+                    ConfigErrorKind::TemplateCycle(chain),
+                ));
+            }
+
+            match self.templates.get(name) {
+                Some(template) => {
+                    chain.push(name.clone());
+                    table = template;
+                    key_path = KeyPath::root().key("templates").key(&**name);
+                }
+                None => {
+                    return Err(ConfigError::new(
+                        extends_key_path,
+                        ConfigErrorKind::UnknownTemplate(name.clone()),
+                    ))
+                }
+            }
+        }
     }
 
-    if let Err(error) = env::set_current_dir(current_dir) {
-        eprintln_red!(
-            "Cannot move back to the previous working directory: {}",
-            error
+    /// The test with the keys it inherits from the templates it extends and from the
+    /// `[defaults]` table. Also return the path of the keys that are entirely inherited.
+    fn apply(
+        &self,
+        test: &Value,
+        key_path: &KeyPath,
+    ) -> Result<(Value, HashMap<String, KeyPath>), ConfigError> {
+        let mut resolved = match *test {
+            Value::Table(ref table) => table.clone(),
+            _ => return Ok((test.clone(), HashMap::new())),
+        };
+        let mut origins = HashMap::new();
+
+        for name in self.template_chain(&resolved, key_path, vec![])? {
+            let origin = KeyPath::root().key("templates").key(&*name);
+            inherit(&mut resolved, &self.templates[&name], &origin, &mut origins);
+        }
+        inherit(
+            &mut resolved,
+            &self.keys,
+            &KeyPath::root().key("defaults"),
+            &mut origins,
         );
-        return Err(());
+        resolved.remove("extends");
+
+        Ok((Value::Table(resolved), origins))
     }
+}
 
-    Ok(result)
+/// Inherit the keys of a template or of the `[defaults]` table, whose path is `origin`. The
+/// keys that are entirely inherited are added to `origins`.
+fn inherit(
+    table: &mut toml::value::Table,
+    inherited: &toml::value::Table,
+    origin: &KeyPath,
+    origins: &mut HashMap<String, KeyPath>,
+) {
+    for key in inherited.keys() {
+        if key != "extends" && !table.contains_key(key) {
+            origins.insert(key.clone(), origin.key(&**key));
+        }
+    }
+
+    merge_tables(table, inherited, "");
 }
 
-pub fn run_config_root(
-    config_filename: Option<&OsStr>,
-    filter: &Option<Regex>,
-) -> Result<RunConfigResult, ()> {
-    let config_filename = match config_filename.map(PathBuf::from).or_else(find_config_file) {
-        Some(config_filename) => config_filename,
+/// Merge an inherited table into a table. The values of `table` are kept, except that:
+///
+/// - the tables are merged key by key,
+/// - the `env` arrays are merged by variable name,
+/// - the inherited `matrix.exclude` and `matrix.include` rules are added.
+///
+/// The other arrays, like `command`, are not merged.
+fn merge_tables(table: &mut toml::value::Table, inherited: &toml::value::Table, parent: &str) {
+    for (key, inherited_value) in inherited {
+        if key == "extends" {
+            continue;
+        }
+
+        if !table.contains_key(key) {
+            table.insert(key.clone(), inherited_value.clone());
+            continue;
+        }
+
+        match (table.get_mut(key).unwrap(), inherited_value) {
+            (Value::Table(value), Value::Table(inherited)) => {
+                merge_tables(value, inherited, key)
+            }
+            (Value::Array(value), Value::Array(inherited))
+                if parent.is_empty() && key == "env" =>
+            {
+                // The inherited variables are after the ones of the table, so that the errors
+                // in the table have the right index.
+                let inherited: Vec<_> = inherited
+                    .iter()
+                    .filter(|env| {
+                        let name = env.get("name");
+                        name.is_none() || !value.iter().any(|own| own.get("name") == name)
+                    })
+                    .cloned()
+                    .collect();
+                value.extend(inherited);
+            }
+            (Value::Array(value), Value::Array(inherited))
+                if parent == "matrix" && (key == "exclude" || key == "include") =>
+            {
+                value.extend(inherited.iter().cloned());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Add an error, unless the same error was already added. An error in a template or in the
+/// `[defaults]` table is otherwise reported for every test using it.
+fn push_error(errors: &mut Vec<ConfigError>, error: ConfigError) {
+    let message = error.kind.to_string();
+
+    if !errors
+        .iter()
+        .any(|other| other.key_path == error.key_path && other.kind.to_string() == message)
+    {
+        errors.push(error);
+    }
+}
+
+#[derive(Default)]
+struct ParseResult {
+    tests: Vec<Test<String, String, String>>,
+    setup: Vec<Test<String, String, String>>,
+    teardown: Vec<Test<String, String, String>>,
+    services: Vec<Service>,
+    includes: Vec<PathBuf>,
+}
+
+/// Keep the value of a result, or move its errors to `errors`.
+fn collect_errors<T>(result: Result<T, Vec<ConfigError>>, errors: &mut Vec<ConfigError>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(new_errors) => {
+            errors.extend(new_errors);
+            None
+        }
+    }
+}
+
+fn toml_value_to_liquid(toml_value: &Value) -> liquid::Value {
+    match *toml_value {
+        Value::String(ref value) => liquid::Value::scalar(value.clone()),
+        Value::Integer(value) => liquid::Value::scalar(value as f32),
+        Value::Float(value) => liquid::Value::scalar(value as f32),
+        Value::Boolean(value) => liquid::Value::scalar(value),
+        Value::Datetime(ref value) => liquid::Value::scalar(value.to_string()),
+        Value::Array(ref value) => {
+            liquid::Value::Array(value.iter().map(toml_value_to_liquid).collect())
+        }
+        Value::Table(ref value) => liquid::Value::Object(
+            value
+                .iter()
+                .map(|(key, value)| (key.clone(), toml_value_to_liquid(value)))
+                .collect(),
+        ),
+    }
+}
+
+pub fn find_config_file() -> Option<PathBuf> {
+    let current_dir = env::current_dir().unwrap();
+    let mut current = &*current_dir;
+
+    loop {
+        let config_file = current.join(CONFIG_FILE_NAME);
+        if config_file.metadata().is_ok() {
+            return Some(config_file.to_path_buf());
+        }
+
+        match current.parent() {
+            Some(parent) => {
+                current = parent;
+            }
+            None => {
+                break;
+            }
+        }
+    }
+
+    None
+}
+
+/// Parse an optional duration like "10m" or "1h 30m".
+fn duration_from_toml(
+    table: &Value,
+    key_path: &KeyPath,
+    key: &str,
+) -> Result<Option<Duration>, ConfigError> {
+    match table.get(key) {
+        Some(value) => match value.as_str().map(parse_duration) {
+            Some(Ok(duration)) => Ok(Some(duration)),
+            Some(Err(error)) => Err(ConfigError::new(
+                key_path.key(key),
+                ConfigErrorKind::InvalidDuration(error),
+            )),
+            None => Err(ConfigError::new(
+                key_path.key(key),
+                ConfigErrorKind::InvalidType("a string like \"10m\""),
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+fn test_from_toml(
+    test: &Value,
+    key_path: &KeyPath,
+) -> Result<Test<String, String, String>, Vec<ConfigError>> {
+    let mut errors = vec![];
+
+    let name = match test.get("name") {
+        Some(name) => match name.as_str() {
+            Some(name) => Some(name),
+            None => {
+                errors.push(ConfigError::new(
+                    key_path.key("name"),
+                    ConfigErrorKind::InvalidType("a string"),
+                ));
+                None
+            }
+        },
         None => {
-            eprintln_red!("{} not found", CONFIG_FILE_NAME);
-            return Err(());
+            errors.push(ConfigError::new(
+                key_path.key("name"),
+                ConfigErrorKind::Missing,
+            ));
+            None
+        }
+    };
+
+    let command = match (test.get("command"), test.get("script")) {
+        (Some(command), None) => TestCommand::from_toml(command, key_path.key("command"))
+            .map_err(|error| errors.push(error))
+            .ok(),
+        (None, Some(Value::String(script))) => Some(TestCommand::Script(script.clone())),
+        (None, Some(_)) => {
+            errors.push(ConfigError::new(
+                key_path.key("script"),
+                ConfigErrorKind::InvalidType("a string"),
+            ));
+            None
+        }
+        (Some(_), Some(_)) => {
+            errors.push(ConfigError::new(
+                key_path.key("script"),
+                ConfigErrorKind::Conflict("command"),
+            ));
+            None
+        }
+        (None, None) => {
+            errors.push(ConfigError::new(
+                key_path.key("command"),
+                ConfigErrorKind::Missing,
+            ));
+            None
+        }
+    };
+
+    // `shell = true` runs an array command with the shell, and an array gives the shell running
+    // the string commands and the scripts.
+    let (use_shell, shell) = match test.get("shell") {
+        Some(&Value::Boolean(use_shell)) => (use_shell, None),
+        Some(shell) => {
+            let shell: Option<Vec<_>> = shell.as_array().and_then(|shell| {
+                shell
+                    .iter()
+                    .map(|arg| arg.as_str().map(|s| s.to_string()))
+                    .collect()
+            });
+            match shell {
+                Some(ref shell) if !shell.is_empty() => (false, Some(shell.clone())),
+                _ => {
+                    errors.push(ConfigError::new(
+                        key_path.key("shell"),
+                        ConfigErrorKind::InvalidType("a boolean or a non-empty array of strings"),
+                    ));
+                    (false, None)
+                }
+            }
+        }
+        None => (false, None),
+    };
+
+    // The arguments appended to the command, so that a test can add some to the command of the
+    // template it extends.
+    let args = match test.get("args") {
+        Some(args) => {
+            let args: Option<Vec<_>> = args.as_array().and_then(|args| {
+                args.iter()
+                    .map(|arg| arg.as_str().map(|s| s.to_string()))
+                    .collect()
+            });
+            args.unwrap_or_else(|| {
+                errors.push(ConfigError::new(
+                    key_path.key("args"),
+                    ConfigErrorKind::InvalidType("an array of strings"),
+                ));
+                vec![]
+            })
+        }
+        None => vec![],
+    };
+
+    let tags = match test.get("tags") {
+        Some(tags) => {
+            let tags: Option<Vec<_>> = tags.as_array().and_then(|tags| {
+                tags.iter()
+                    .map(|tag| tag.as_str().map(|s| s.to_string()))
+                    .collect()
+            });
+            tags.unwrap_or_else(|| {
+                errors.push(ConfigError::new(
+                    key_path.key("tags"),
+                    ConfigErrorKind::InvalidType("an array of strings"),
+                ));
+                vec![]
+            })
+        }
+        None => vec![],
+    };
+
+    let cwd = match test.get("cwd") {
+        Some(cwd) => match cwd.as_str() {
+            Some(cwd) => Some(PathBuf::from(cwd)),
+            None => {
+                errors.push(ConfigError::new(
+                    key_path.key("cwd"),
+                    ConfigErrorKind::InvalidType("a string"),
+                ));
+                None
+            }
+        },
+        None => None,
+    };
+
+    let clear_env = match test.get("clear_env") {
+        Some(clear_env) => match clear_env.as_bool() {
+            Some(clear_env) => clear_env,
+            None => {
+                errors.push(ConfigError::new(
+                    key_path.key("clear_env"),
+                    ConfigErrorKind::InvalidType("a boolean"),
+                ));
+                false
+            }
+        },
+        None => false,
+    };
+
+    let env = match test.get("env") {
+        Some(env) => match env.as_array() {
+            Some(env) => env.iter()
+                .enumerate()
+                .filter_map(|(i, table)| {
+                    collect_errors(
+                        env_from_table(table, &key_path.key("env").index(i)),
+                        &mut errors,
+                    )
+                })
+                .collect(),
+            None => {
+                errors.push(ConfigError::new(
+                    key_path.key("env"),
+                    ConfigErrorKind::InvalidType("an array of tables"),
+                ));
+                vec![]
+            }
+        },
+        None => vec![],
+    };
+
+    let exit_codes = collect_errors(exit_codes_from_toml(test, key_path), &mut errors);
+
+    let timeout = duration_from_toml(test, key_path, "timeout")
+        .map_err(|error| errors.push(error))
+        .ok()
+        .and_then(|timeout| timeout);
+    let timeout_grace = duration_from_toml(test, key_path, "timeout_grace")
+        .map_err(|error| errors.push(error))
+        .ok()
+        .and_then(|timeout_grace| timeout_grace)
+        .unwrap_or(DEFAULT_TIMEOUT_GRACE);
+
+    let retries = match test.get("retries") {
+        Some(retries) => match retries.as_integer() {
+            Some(retries) if retries >= 0 => retries as usize,
+            _ => {
+                errors.push(ConfigError::new(
+                    key_path.key("retries"),
+                    ConfigErrorKind::InvalidType("a non-negative integer"),
+                ));
+                0
+            }
+        },
+        None => 0,
+    };
+    let retry_delay = duration_from_toml(test, key_path, "retry_delay")
+        .map_err(|error| errors.push(error))
+        .ok()
+        .and_then(|retry_delay| retry_delay)
+        .unwrap_or_else(|| Duration::from_secs(0));
+
+    let mut hook = |key: &str| match test.get(key) {
+        Some(hook) => TestCommand::from_toml(hook, key_path.key(key))
+            .map_err(|error| errors.push(error))
+            .ok(),
+        None => None,
+    };
+    let before = hook("before");
+    let after = hook("after");
+
+    match (name, command) {
+        (Some(name), Some(command)) if errors.is_empty() => {
+            // The commands before and after the test are run by the shell of the test, but
+            // they are single commands.
+            let hook_command_line = |hook: Option<TestCommand>| match hook {
+                Some(hook) => {
+                    let (command, hook_shell) = hook.command_line(&[], use_shell, &shell);
+                    let mut command_line = hook_shell.unwrap_or_default();
+                    command_line.extend(command);
+                    command_line
+                }
+                None => vec![],
+            };
+            let before = hook_command_line(before);
+            let after = hook_command_line(after);
+            let (command, shell) = command.command_line(&args, use_shell, &shell);
+
+            let mut test = Test::new(name, command, clear_env, env);
+            test.shell = shell;
+            test.before = before;
+            test.after = after;
+            test.timeout = timeout;
+            test.timeout_grace = timeout_grace;
+            test.exit_codes = exit_codes.unwrap_or_default();
+            test.tags = tags;
+            test.retries = retries;
+            test.retry_delay = retry_delay;
+            test.cwd = cwd;
+
+            Ok(test)
         }
+        _ => Err(errors),
+    }
+}
+
+fn exit_code_from_toml(value: &Value) -> Option<i32> {
+    let code = value.as_integer()?;
+
+    // The conversion is lossless only if the code fits in an i32.
+    if i64::from(code as i32) == code {
+        Some(code as i32)
+    } else {
+        None
+    }
+}
+
+/// Parse the `expect_exit_code`, `skip_exit_code` and `error_exit_code` keys of a test.
+fn exit_codes_from_toml(test: &Value, key_path: &KeyPath) -> Result<ExitCodes, Vec<ConfigError>> {
+    let mut exit_codes = ExitCodes::default();
+    let mut errors = vec![];
+
+    if let Some(expected) = test.get("expect_exit_code") {
+        let expected = match *expected {
+            Value::Array(ref codes) if !codes.is_empty() => {
+                codes.iter().map(exit_code_from_toml).collect()
+            }
+            ref code => exit_code_from_toml(code).map(|code| vec![code]),
+        };
+
+        match expected {
+            Some(expected) => exit_codes.expected = expected,
+            None => errors.push(ConfigError::new(
+                key_path.key("expect_exit_code"),
+                ConfigErrorKind::InvalidType("an exit code or a non-empty array of exit codes"),
+            )),
+        }
+    }
+
+    let mut optional_code = |key: &str| match test.get(key) {
+        Some(code) => {
+            let code = exit_code_from_toml(code);
+            if code.is_none() {
+                errors.push(ConfigError::new(
+                    key_path.key(key),
+                    ConfigErrorKind::InvalidType("an exit code"),
+                ));
+            }
+            code
+        }
+        None => None,
     };
 
-    run_config(&config_filename, filter)
+    exit_codes.skip = optional_code("skip_exit_code");
+    exit_codes.error = optional_code("error_exit_code");
+
+    if errors.is_empty() {
+        Ok(exit_codes)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parse the `expect_stdout`, `expect_stderr`, `expect_stdout_file` and `expect_stderr_file`
+/// keys of a test.
+fn expectations_from_toml(
+    test: &Value,
+    key_path: &KeyPath,
+) -> Result<Vec<ExpectationTemplate>, Vec<ConfigError>> {
+    let parser = template::parser();
+    let mut expectations = vec![];
+    let mut errors = vec![];
+
+    let keys = [
+        ("expect_stdout", Stream::Stdout, false),
+        ("expect_stderr", Stream::Stderr, false),
+        ("expect_stdout_file", Stream::Stdout, true),
+        ("expect_stderr_file", Stream::Stderr, true),
+    ];
+
+    for &(key, stream, is_file) in &keys {
+        let value = match test.get(key) {
+            Some(value) => value,
+            None => continue,
+        };
+        let mut key_path = key_path.key(key);
+
+        let expected = match (value, is_file) {
+            (Value::String(path), true) => Some((MatchKind::File, &**path)),
+            (Value::String(expected), false) => Some((MatchKind::Exact, &**expected)),
+            (Value::Table(table), false) if table.len() == 1 => {
+                let (name, value) = table.iter().next().unwrap();
+                let kind = match &**name {
+                    "exact" => Some(MatchKind::Exact),
+                    "contains" => Some(MatchKind::Contains),
+                    "regex" => Some(MatchKind::Regex),
+                    _ => None,
+                };
+                key_path = key_path.key(&**name);
+
+                match (kind, value.as_str()) {
+                    (Some(kind), Some(expected)) => Some((kind, expected)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        match expected {
+            Some((kind, expected)) => match parser.parse(expected) {
+                Ok(expected) => expectations.push(ExpectationTemplate {
+                    stream,
+                    kind,
+                    expected,
+                    key_path,
+                }),
+                Err(error) => {
+                    errors.push(ConfigError::new(key_path, ConfigErrorKind::Template(error)))
+                }
+            },
+            None => errors.push(ConfigError::new(
+                key_path,
+                ConfigErrorKind::InvalidType(if is_file {
+                    "a string"
+                } else {
+                    "a string or a table with one of the keys `exact`, `contains` or `regex`"
+                }),
+            )),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(expectations)
+    } else {
+        Err(errors)
+    }
+}
+
+fn stdin_from_toml(test: &Value, key_path: &KeyPath) -> Result<Option<StdinTemplate>, ConfigError> {
+    let parse = |template: &str, key_path: KeyPath| {
+        template::parser()
+            .parse(template)
+            .map_err(|error| ConfigError::new(key_path, ConfigErrorKind::Template(error)))
+    };
+
+    match (test.get("stdin"), test.get("stdin_file")) {
+        (Some(_), Some(_)) => Err(ConfigError::new(
+            key_path.key("stdin_file"),
+            ConfigErrorKind::Conflict("stdin"),
+        )),
+        (Some(stdin), None) => match stdin.as_str() {
+            Some("null") => Ok(Some(StdinTemplate::Null)),
+            Some(text) => Ok(Some(StdinTemplate::Text(parse(text, key_path.key("stdin"))?))),
+            None => Err(ConfigError::new(
+                key_path.key("stdin"),
+                ConfigErrorKind::InvalidType("a string"),
+            )),
+        },
+        (None, Some(stdin_file)) => match stdin_file.as_str() {
+            Some(path) => Ok(Some(StdinTemplate::File(
+                parse(path, key_path.key("stdin_file"))?,
+            ))),
+            None => Err(ConfigError::new(
+                key_path.key("stdin_file"),
+                ConfigErrorKind::InvalidType("a string"),
+            )),
+        },
+        (None, None) => Ok(None),
+    }
+}
+
+fn env_from_table(table: &Value, key_path: &KeyPath) -> Result<(String, String), Vec<ConfigError>> {
+    let string = |key: &str| match table.get(key) {
+        Some(value) => value.as_str().map(|value| value.to_string()).ok_or_else(|| {
+            ConfigError::new(key_path.key(key), ConfigErrorKind::InvalidType("a string"))
+        }),
+        None => Err(ConfigError::new(key_path.key(key), ConfigErrorKind::Missing)),
+    };
+
+    match (string("name"), string("value")) {
+        (Ok(name), Ok(value)) => Ok((name, value)),
+        (name, value) => Err(name.err().into_iter().chain(value.err()).collect()),
+    }
+}
+
+fn render(
+    template: &Template,
+    key_path: KeyPath,
+    variables_values: &liquid::Object,
+) -> Result<String, Vec<ConfigError>> {
+    template
+        .render(variables_values)
+        .map_err(|error| vec![ConfigError::new(key_path, ConfigErrorKind::Render(error))])
+}
+
+fn render_expectation(
+    template: &ExpectationTemplate,
+    variables_values: &liquid::Object,
+) -> Result<Expectation, Vec<ConfigError>> {
+    let expected = render(&template.expected, template.key_path.clone(), variables_values)?;
+
+    let matcher = match template.kind {
+        MatchKind::Exact => Matcher::Exact(expected),
+        MatchKind::Contains => Matcher::Contains(expected),
+        MatchKind::Regex => Matcher::Regex(Regex::new(&expected).map_err(|error| {
+            vec![ConfigError::new(
+                template.key_path.clone(),
+                ConfigErrorKind::InvalidRegex(error),
+            )]
+        })?),
+        MatchKind::File => Matcher::File(PathBuf::from(expected)),
+    };
+
+    Ok(Expectation {
+        stream: template.stream,
+        matcher,
+    })
+}
+
+/// Generate the test of one combination of the variables of the matrix.
+fn gen_test(
+    test_template: &TestTemplate,
+    key_path: &KeyPath,
+    config_dir: &Path,
+    variables_values: &mut liquid::Object,
+) -> Result<Test<String, String, String>, Vec<ConfigError>> {
+    let name = render(&test_template.name, key_path.key("name"), variables_values)?;
+
+    variables_values.insert("name".to_string(), liquid::Value::scalar(name.clone()));
+
+    let command = test_template
+        .command
+        .iter()
+        .zip(&test_template.command_key_paths)
+        .map(|(arg_template, arg_key_path)| {
+            render(arg_template, arg_key_path.clone(), variables_values)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let env = test_template
+        .env
+        .iter()
+        .enumerate()
+        .map(|(i, (name_template, value_template))| {
+            let env_key_path = key_path.key("env").index(i);
+            let name = render(name_template, env_key_path.key("name"), variables_values)?;
+            let value = render(value_template, env_key_path.key("value"), variables_values)?;
+
+            Ok((name, value))
+        })
+        .collect::<Result<Vec<_>, Vec<ConfigError>>>()?;
+
+    let expectations = test_template
+        .expectations
+        .iter()
+        .map(|expectation| render_expectation(expectation, variables_values))
+        .collect::<Result<Vec<_>, Vec<ConfigError>>>()?;
+
+    let tags = test_template
+        .tags
+        .iter()
+        .enumerate()
+        .map(|(i, tag_template)| render(tag_template, key_path.key("tags").index(i), variables_values))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let cwd = match test_template.cwd {
+        Some(ref cwd_template) => {
+            let cwd = config_dir.join(render(cwd_template, key_path.key("cwd"), variables_values)?);
+            if !cwd.is_dir() {
+                return Err(vec![ConfigError::new(
+                    key_path.key("cwd"),
+                    ConfigErrorKind::MissingDirectory(cwd),
+                )]);
+            }
+            Some(cwd)
+        }
+        None => None,
+    };
+
+    let render_hook = |templates: &[Template], key: &str, variables_values: &liquid::Object| {
+        templates
+            .iter()
+            .map(|arg_template| render(arg_template, key_path.key(key), variables_values))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let before = render_hook(&test_template.before, "before", variables_values)?;
+    let after = render_hook(&test_template.after, "after", variables_values)?;
+
+    let stdin = match test_template.stdin {
+        Some(StdinTemplate::Null) => Stdin::Null,
+        Some(StdinTemplate::Text(ref text)) => {
+            Stdin::Text(render(text, key_path.key("stdin"), variables_values)?)
+        }
+        Some(StdinTemplate::File(ref path)) => Stdin::File(PathBuf::from(render(
+            path,
+            key_path.key("stdin_file"),
+            variables_values,
+        )?)),
+        None => Stdin::Inherit,
+    };
+
+    let mut test = Test::new(name, command, test_template.clear_env, env);
+    test.shell = test_template.shell.clone();
+    test.stdin = stdin;
+    test.cwd = cwd;
+    test.timeout = test_template.timeout;
+    test.timeout_grace = test_template.timeout_grace;
+    test.expectations = expectations;
+    test.exit_codes = test_template.exit_codes.clone();
+    test.tags = tags;
+    test.retries = test_template.retries;
+    test.retry_delay = test_template.retry_delay;
+    test.before = before;
+    test.after = after;
+
+    Ok(test)
+}
+
+fn cartesian_product(
+    dimensions: &[Dimension],
+    variables_values: &mut liquid::Object,
+    combinations: &mut Vec<liquid::Object>,
+) {
+    if dimensions.is_empty() {
+        combinations.push(variables_values.clone());
+    } else {
+        for values in &dimensions[0].values {
+            for (name, value) in values {
+                variables_values.insert(name.clone(), value.clone());
+            }
+            cartesian_product(&dimensions[1..], variables_values, combinations);
+        }
+    }
+}
+
+/// Parse a configuration file. The errors are returned along with what could be parsed, so that
+/// every error can be reported at once.
+fn parse_config(config_filename: &Path) -> (ParseResult, Vec<ConfigError>) {
+    let mut errors = vec![];
+    let (result, lines) = parse_config_text(config_filename, &mut errors);
+
+    for error in &mut errors {
+        error.config_file = config_filename.to_path_buf();
+        if error.line.is_none() {
+            error.line = lines.as_ref().and_then(|lines| lines.find(&error.key_path));
+        }
+    }
+
+    (result, errors)
+}
+
+fn parse_config_text(
+    config_filename: &Path,
+    errors: &mut Vec<ConfigError>,
+) -> (ParseResult, Option<SourceLines>) {
+    let mut config_text = String::new();
+
+    if let Err(error) =
+        File::open(config_filename).and_then(|mut file| file.read_to_string(&mut config_text))
+    {
+        errors.push(ConfigError::new(KeyPath::root(), ConfigErrorKind::Io(error)));
+        return (Default::default(), None);
+    }
+
+    let config_parsed = match config_text.parse::<Value>() {
+        Ok(config) => config,
+        Err(error) => {
+            let mut config_error = ConfigError::new(KeyPath::root(), ConfigErrorKind::Parse(error));
+            if let ConfigErrorKind::Parse(ref error) = *config_error.kind {
+                config_error.line = error.line_col().map(|(line, _)| line + 1);
+            }
+            errors.push(config_error);
+            return (Default::default(), None);
+        }
+    };
+
+    let lines = SourceLines::new(&config_text);
+    let defaults = FileDefaults::from_toml(&config_parsed, errors);
+    let config_dir = config_dir(config_filename);
+    let tests = parse_tests(&config_parsed, "tests", &defaults, config_dir, errors);
+    let setup = parse_tests(&config_parsed, "setup", &defaults, config_dir, errors);
+    let teardown = parse_tests(&config_parsed, "teardown", &defaults, config_dir, errors);
+    let services = parse_services(&config_parsed, &defaults, config_dir, errors);
+
+    let mut collected_includes = vec![];
+
+    match config_parsed.get("includes").map(|includes| (includes, includes.as_array())) {
+        Some((_, Some(includes))) => for (index, include) in includes.iter().enumerate() {
+            let key_path = KeyPath::root().key("includes").index(index);
+            let error = |kind| ConfigError::new(key_path.clone(), kind);
+
+            match Value::as_str(include) {
+                Some(include) => match glob(&include_pattern(config_dir, include)) {
+                    Ok(paths) => for path in paths {
+                        match path {
+                            Ok(path) => collected_includes.push(path),
+                            Err(err) => errors.push(error(ConfigErrorKind::Glob(err))),
+                        }
+                    },
+                    Err(err) => errors.push(error(ConfigErrorKind::InvalidGlob(err))),
+                },
+                None => errors.push(error(ConfigErrorKind::InvalidType("a string"))),
+            }
+        },
+        Some((_, None)) => errors.push(ConfigError::new(
+            KeyPath::root().key("includes"),
+            ConfigErrorKind::InvalidType("an array of strings"),
+        )),
+        None => {}
+    }
+
+    let result = ParseResult {
+        tests,
+        setup,
+        teardown,
+        services,
+        includes: collected_includes,
+    };
+
+    (result, Some(lines))
+}
+
+/// The tables of an array of tables of a configuration file, like `tests`. There are none if the
+/// key is missing or invalid.
+fn tables<'a>(config: &'a Value, key: &str, errors: &mut Vec<ConfigError>) -> &'a [Value] {
+    match config.get(key).map(|tables| (tables, tables.as_array())) {
+        Some((_, Some(tables))) => tables,
+        Some((_, None)) => {
+            errors.push(ConfigError::new(
+                KeyPath::root().key(key),
+                ConfigErrorKind::InvalidType("an array of tables"),
+            ));
+            &[]
+        }
+        None => &[],
+    }
+}
+
+/// Parse the tests of an array of tables of a configuration file: `tests`, `setup` or
+/// `teardown`.
+fn parse_tests(
+    config: &Value,
+    key: &str,
+    defaults: &FileDefaults,
+    config_dir: &Path,
+    errors: &mut Vec<ConfigError>,
+) -> Vec<Test<String, String, String>> {
+    let mut collected_tests = vec![];
+
+    for (index, test) in tables(config, key, errors).iter().enumerate() {
+        parse_table(
+            test,
+            key,
+            index,
+            defaults,
+            config_dir,
+            &mut collected_tests,
+            errors,
+        );
+    }
+
+    collected_tests
+}
+
+/// Parse the services of a configuration file. They are tests with a readiness check.
+fn parse_services(
+    config: &Value,
+    defaults: &FileDefaults,
+    config_dir: &Path,
+    errors: &mut Vec<ConfigError>,
+) -> Vec<Service> {
+    let mut collected_services = vec![];
+
+    for (index, service) in tables(config, "services", errors).iter().enumerate() {
+        let key_path = KeyPath::root().key("services").index(index);
+        let mut tests = vec![];
+        parse_table(
+            service,
+            "services",
+            index,
+            defaults,
+            config_dir,
+            &mut tests,
+            errors,
+        );
+
+        let ready = readiness_from_toml(service, &key_path);
+        let ready_timeout = duration_from_toml(service, &key_path, "ready_timeout");
+
+        match (ready, ready_timeout) {
+            (Ok(ready), Ok(ready_timeout)) => {
+                let ready_timeout = ready_timeout.unwrap_or(DEFAULT_READY_TIMEOUT);
+                collected_services.extend(tests.into_iter().map(|test| Service {
+                    test,
+                    ready: ready.clone(),
+                    ready_timeout,
+                }));
+            }
+            (ready, ready_timeout) => {
+                for mut error in ready.err().into_iter().chain(ready_timeout.err()) {
+                    error.test_index = Some(index);
+                    error.test_name = service
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .map(|name| name.to_string());
+                    push_error(errors, error);
+                }
+            }
+        }
+    }
+
+    collected_services
+}
+
+/// Parse the readiness check of a service, like `ready = { port = 8080 }`. A command is run by
+/// `sh -c` if it is a string, and is not a template.
+fn readiness_from_toml(
+    service: &Value,
+    key_path: &KeyPath,
+) -> Result<Option<Readiness>, ConfigError> {
+    let key_path = key_path.key("ready");
+    let invalid = || {
+        ConfigError::new(
+            key_path.clone(),
+            ConfigErrorKind::InvalidType("a table with one of port, log or command"),
+        )
+    };
+
+    let ready = match service.get("ready") {
+        Some(Value::Table(ready)) if ready.len() == 1 => ready,
+        Some(_) => return Err(invalid()),
+        None => return Ok(None),
+    };
+
+    let ready = match (ready.get("port"), ready.get("log"), ready.get("command")) {
+        (Some(port), None, None) => match port.as_integer() {
+            Some(port) if port > 0 && port < 65_536 => {
+                Readiness::Port(port as u16)
+            }
+            _ => {
+                return Err(ConfigError::new(
+                    key_path.key("port"),
+                    ConfigErrorKind::InvalidType("a port number"),
+                ))
+            }
+        },
+        (None, Some(log), None) => match log.as_str() {
+            Some(regex) => Readiness::Log(Regex::new(regex).map_err(|error| {
+                ConfigError::new(key_path.key("log"), ConfigErrorKind::InvalidRegex(error))
+            })?),
+            None => {
+                return Err(ConfigError::new(
+                    key_path.key("log"),
+                    ConfigErrorKind::InvalidType("a string"),
+                ))
+            }
+        },
+        (None, None, Some(command)) => {
+            match TestCommand::from_toml(command, key_path.key("command"))? {
+                TestCommand::Args(command) => Readiness::Command(command),
+                TestCommand::Script(script) => Readiness::Command(
+                    DEFAULT_SHELL
+                        .iter()
+                        .map(|arg| arg.to_string())
+                        .chain(Some(script))
+                        .collect(),
+                ),
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    Ok(Some(ready))
+}
+
+/// Parse the table at `index` in the array of tables `key`, with the `[defaults]` and the
+/// templates it extends. The errors are reported with the index and the name of the test.
+fn parse_table(
+    test: &Value,
+    key: &str,
+    index: usize,
+    defaults: &FileDefaults,
+    config_dir: &Path,
+    collected_tests: &mut Vec<Test<String, String, String>>,
+    errors: &mut Vec<ConfigError>,
+) {
+    let key_path = KeyPath::root().key(key).index(index);
+    let mut test_errors = vec![];
+
+    // The setup and teardown commands are named after their key by default.
+    let named;
+    let test = match *test {
+        Value::Table(ref table)
+            if (key == "setup" || key == "teardown") && !table.contains_key("name") =>
+        {
+            let mut table = table.clone();
+            table.insert("name".to_string(), Value::String(key.to_string()));
+            named = Value::Table(table);
+            &named
+        }
+        _ => test,
+    };
+
+    let origins = match defaults.apply(test, &key_path) {
+        Ok((test, origins)) => {
+            parse_test(
+                &test,
+                &key_path,
+                defaults,
+                config_dir,
+                collected_tests,
+                &mut test_errors,
+            );
+            origins
+        }
+        Err(error) => {
+            test_errors.push(error);
+            HashMap::new()
+        }
+    };
+
+    let test_name = test.get("name").and_then(Value::as_str);
+    for mut error in test_errors {
+        // The errors in an inherited key are reported where the key is.
+        let origin = match error.key_path.0.get(2) {
+            Some(KeyPart::Key(inherited_key)) => origins.get(inherited_key).cloned(),
+            _ => None,
+        };
+        if let Some(origin) = origin {
+            let mut parts = origin.0;
+            parts.extend_from_slice(&error.key_path.0[3..]);
+            error.key_path = KeyPath(parts);
+        }
+
+        error.test_index = Some(index);
+        error.test_name = test_name.map(|name| name.to_string());
+        push_error(errors, error);
+    }
+}
+
+/// Parse a test and generate every element of its matrix.
+fn parse_test(
+    test: &Value,
+    key_path: &KeyPath,
+    defaults: &FileDefaults,
+    config_dir: &Path,
+    collected_tests: &mut Vec<Test<String, String, String>>,
+    errors: &mut Vec<ConfigError>,
+) {
+    let command_key = if test.get("script").is_some() {
+        "script"
+    } else {
+        "command"
+    };
+    let test_template = collect_errors(test_from_toml(test, key_path), errors)
+        .and_then(|mut test| {
+            for (name, value) in &defaults.env {
+                if !test.env.iter().any(|(test_name, _)| test_name == name) {
+                    test.env.push((name.clone(), value.clone()));
+                }
+            }
+
+            collect_errors(
+                TestTemplate::try_from_test(&test, key_path, command_key),
+                errors,
+            )
+        });
+    let expectations = collect_errors(expectations_from_toml(test, key_path), errors);
+    let stdin = stdin_from_toml(test, key_path)
+        .map_err(|error| errors.push(error))
+        .ok();
+    let matrix = collect_errors(
+        Matrix::from_toml(test, key_path, config_dir, &defaults.variables),
+        errors,
+    );
+
+    if let (Some(mut test_template), Some(matrix), Some(expectations), Some(stdin)) =
+        (test_template, matrix, expectations, stdin)
+    {
+        test_template.expectations = expectations;
+        test_template.stdin = stdin;
+
+        for mut variables_values in matrix.combinations() {
+            match gen_test(&test_template, key_path, config_dir, &mut variables_values) {
+                Ok(test) => collected_tests.push(test),
+                Err(error) => {
+                    // The other combinations would most likely have the same error.
+                    errors.extend(error);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The directory containing a configuration file. The tests of a configuration file are
+/// executed from this directory, unless they have another `cwd`.
+pub fn config_dir(config_filename: &Path) -> &Path {
+    match config_filename.parent() {
+        Some(parent) if parent.to_str() != Some("") => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// The include glob patterns are relative to the directory containing the configuration file.
+fn include_pattern(config_dir: &Path, include: &str) -> String {
+    if Path::new(include).is_absolute() {
+        include.to_string()
+    } else {
+        let config_dir = Pattern::escape(&config_dir.to_string_lossy());
+        Path::new(&config_dir).join(include).to_string_lossy().into_owned()
+    }
+}
+
+/// The tests of a configuration file and of its includes, in the order they should be run.
+#[derive(Default)]
+pub struct LoadedConfig {
+    pub tests: Vec<Test<String, String, String>>,
+    /// The tests that don't match the filter.
+    pub ignored: Vec<Test<String, String, String>>,
+    /// The setup and teardown commands of each configuration file, a file before the files it
+    /// includes.
+    pub hooks: Vec<Hooks>,
+}
+
+/// The commands run once before and after the tests of a configuration file, and the services
+/// running during these tests.
+pub struct Hooks {
+    pub config_file: PathBuf,
+    /// The configuration file and the files it includes, directly or not. Their tests depend on
+    /// the setup commands and the services.
+    pub files: Vec<PathBuf>,
+    pub setup: Vec<Test<String, String, String>>,
+    pub teardown: Vec<Test<String, String, String>>,
+    pub services: Vec<Service>,
+}
+
+/// Resolve the paths of a test relative to its configuration file, and apply the options
+/// overriding the keys of every test.
+fn prepare_test(
+    test: &mut Test<String, String, String>,
+    config_filename: &Path,
+    options: &RunOptions,
+) {
+    let config_dir = config_dir(config_filename);
+
+    if test.cwd.is_none() {
+        test.cwd = Some(config_dir.to_path_buf());
+    }
+    test.config_file = Some(config_filename.to_path_buf());
+
+    for expectation in &mut test.expectations {
+        if let Matcher::File(ref mut path) = expectation.matcher {
+            *path = config_dir.join(&*path);
+        }
+    }
+
+    match test.stdin {
+        Stdin::File(ref mut path) => *path = config_dir.join(&*path),
+        Stdin::Inherit if options.ci => test.stdin = Stdin::Null,
+        _ => {}
+    }
+
+    if options.timeout.is_some() {
+        test.timeout = options.timeout;
+    }
+    if let Some(timeout_grace) = options.timeout_grace {
+        test.timeout_grace = timeout_grace;
+    }
+    if let Some(retries) = options.retries {
+        test.retries = retries;
+    }
+}
+
+/// Collect the tests of a configuration file and of its includes. The errors of every
+/// configuration file are collected in `errors`. If `verbose` is true, the included files and
+/// the ignored tests are reported with the human format.
+fn collect_config(
+    config_filename: &Path,
+    options: &RunOptions,
+    verbose: bool,
+    loaded: &mut LoadedConfig,
+    errors: &mut Vec<ConfigError>,
+) {
+    let (result, parse_errors) = parse_config(config_filename);
+    let ParseResult {
+        tests,
+        mut setup,
+        mut teardown,
+        mut services,
+        includes,
+    } = result;
+    errors.extend(parse_errors);
+
+    for hook in setup.iter_mut().chain(&mut teardown) {
+        prepare_test(hook, config_filename, options);
+    }
+    for service in &mut services {
+        prepare_test(&mut service.test, config_filename, options);
+    }
+
+    let hooks_index = loaded.hooks.len();
+    loaded.hooks.push(Hooks {
+        config_file: config_filename.to_path_buf(),
+        files: vec![config_filename.to_path_buf()],
+        setup,
+        teardown,
+        services,
+    });
+
+    for include in &includes {
+        match options.format {
+            Format::Human if verbose => eprintln_bold!("Including {}", include.display()),
+            Format::Human => {}
+            Format::Json => events::include_loading(include),
+        }
+
+        collect_config(include, options, verbose, loaded, errors);
+
+        if options.format == Format::Json {
+            events::include_loaded(include);
+        }
+    }
+
+    // The hooks of the included files were added after the ones of this file.
+    let included_files: Vec<_> = loaded.hooks[hooks_index + 1..]
+        .iter()
+        .map(|hooks| hooks.config_file.clone())
+        .collect();
+    loaded.hooks[hooks_index].files.extend(included_files);
+
+    for mut test in tests {
+        prepare_test(&mut test, config_filename, options);
+
+        let ignored = !options.selects(&test);
+
+        match options.format {
+            Format::Human if verbose && ignored => eprintln_bold!("Test {} ignored", test.name),
+            Format::Human => {}
+            Format::Json => events::test_discovered(&test, ignored),
+        }
+
+        if ignored {
+            loaded.ignored.push(test);
+        } else {
+            loaded.tests.push(test);
+        }
+    }
+}
+
+/// Load the tests of a configuration file and of its includes, without running them.
+pub fn load_config(
+    config_filename: &Path,
+    options: &RunOptions,
+    verbose: bool,
+) -> Result<LoadedConfig, Vec<ConfigError>> {
+    let mut loaded = Default::default();
+    let mut errors = vec![];
+
+    collect_config(config_filename, options, verbose, &mut loaded, &mut errors);
+
+    if options.failed_first {
+        // The sort is stable, so the tests keep their order otherwise.
+        let last_run = &options.last_run;
+        loaded.tests.sort_by_key(|test| !last_run.failed(test));
+    }
+
+    if errors.is_empty() {
+        Ok(loaded)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Run the tests of a configuration file and of its includes. No test is run if there is any
+/// error in the configuration files.
+///
+/// The setup commands of each configuration file are run before its tests and the tests of the
+/// files it includes, and the teardown commands after them, in the reverse order. The setup
+/// commands of a file are only run if some of these tests are run. Its services are started after
+/// its setup commands, and stopped before its teardown commands. If a setup command fails or a
+/// service isn't ready, these tests are not run. The teardown commands of a file are run once its
+/// setup commands were started, even if tests failed or Ctrl-C was pressed.
+pub fn run_config(
+    config_filename: &Path,
+    options: &RunOptions,
+) -> Result<RunConfigResult, Vec<ConfigError>> {
+    let LoadedConfig {
+        tests,
+        ignored,
+        hooks,
+    } = load_config(config_filename, options, true)?;
+    let mut result: RunConfigResult = Default::default();
+
+    for test in ignored {
+        result
+            .ignored
+            .push((test.config_file.unwrap_or_default(), test.name));
+    }
+
+    let in_files = |test: &Test<String, String, String>, files: &[PathBuf]| match test.config_file {
+        Some(ref config_file) => files.contains(config_file),
+        None => false,
+    };
+
+    // The indexes of the hooks whose setup commands were started with the services that were
+    // started, and the files whose tests depend on a setup command or a service that failed.
+    let mut set_up = vec![];
+    let mut failed_files = vec![];
+
+    for (index, hooks) in hooks.iter().enumerate() {
+        if interrupted() {
+            break;
+        }
+        if failed_files.contains(&hooks.config_file)
+            || !tests.iter().any(|test| in_files(test, &hooks.files))
+        {
+            continue;
+        }
+
+        let mut running = vec![];
+        let mut failed = false;
+
+        for setup in &hooks.setup {
+            let test_run = run_hook(HookKind::Setup, setup, options);
+            if test_run.outcome().is_failure() {
+                result
+                    .hook_failures
+                    .push(TestResult::new(setup, test_run));
+                failed = true;
+                break;
+            }
+        }
+
+        if !failed {
+            for service in &hooks.services {
+                match start_service(service, options) {
+                    Ok(running_service) => running.push((service, running_service)),
+                    Err(failure) => {
+                        result.hook_failures.push(*failure);
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if failed {
+            failed_files.extend(hooks.files.iter().cloned());
+        }
+        set_up.push((index, running));
+    }
+
+    let (tests, not_run): (Vec<_>, Vec<_>) = tests
+        .into_iter()
+        .partition(|test| !in_files(test, &failed_files));
+    for test in not_run {
+        result
+            .not_run
+            .push((test.config_file.unwrap_or_default(), test.name));
+    }
+
+    // No test is started once Ctrl-C was pressed.
+    let test_runs = run_tests(tests, options);
+
+    for (test, test_run) in test_runs {
+        match test_run {
+            Some(test_run) => result.results.push(TestResult::new(&test, test_run)),
+            None => result
+                .not_run
+                .push((test.config_file.unwrap_or_default(), test.name)),
+        }
+    }
+
+    for (index, running) in set_up.into_iter().rev() {
+        let files = &hooks[index].files;
+
+        // The logs of the services are attached to the failures of the tests using them.
+        let failed_in_files = |test_result: &TestResult| {
+            test_result.outcome.is_failure() && files.contains(&test_result.config_file)
+        };
+        let used_by_failures = result.results.iter().any(&failed_in_files);
+
+        for (service, running_service) in running.into_iter().rev() {
+            let (failure, log) = stop_service(service, running_service, used_by_failures, options);
+            result.hook_failures.extend(failure);
+
+            for test_result in &mut result.results {
+                if log.is_empty() {
+                    break;
+                }
+                if failed_in_files(test_result) {
+                    test_result
+                        .service_logs
+                        .push((service.test.name.clone(), log.clone()));
+                }
+            }
+        }
+
+        for teardown in &hooks[index].teardown {
+            let test_run = run_hook(HookKind::Teardown, teardown, options);
+            if test_run.outcome().is_failure() {
+                result
+                    .hook_failures
+                    .push(TestResult::new(teardown, test_run));
+            }
+        }
+    }
+
+    result.interrupted = interrupted();
+    result.rerun_failed = options.rerun_failed;
+
+    Ok(result)
+}
+
+/// The configuration file given on the command line, or the one found in the current directory
+/// or its parents.
+pub fn root_config_file(config_filename: Option<&OsStr>) -> Result<PathBuf, Vec<ConfigError>> {
+    match config_filename.map(PathBuf::from).or_else(find_config_file) {
+        Some(config_filename) => Ok(config_filename),
+        None => {
+            let mut error = ConfigError::new(KeyPath::root(), ConfigErrorKind::NotFound);
+            error.config_file = PathBuf::from(CONFIG_FILE_NAME);
+            Err(vec![error])
+        }
+    }
+}
+
+pub fn run_config_root(
+    config_filename: &Path,
+    options: &RunOptions,
+) -> Result<RunConfigResult, Vec<ConfigError>> {
+    if options.format == Format::Json {
+        events::run_started(config_filename, options.jobs);
+    }
+
+    run_config(config_filename, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn parse_text(name: &str, text: &str) -> (ParseResult, Vec<ConfigError>) {
+        let config_filename =
+            env::temp_dir().join(format!("multitest-{}-{}.toml", name, process::id()));
+        fs::write(&config_filename, text).unwrap();
+        let parsed = parse_config(&config_filename);
+        fs::remove_file(&config_filename).unwrap();
+        parsed
+    }
+
+    /// The sorted names of the tests of a configuration file without errors.
+    fn test_names(name: &str, text: &str) -> Vec<String> {
+        let (result, errors) = parse_text(name, text);
+        let errors: Vec<_> = errors.iter().map(ConfigError::to_string).collect();
+        assert_eq!(errors, Vec::<String>::new());
+
+        let mut names: Vec<_> = result.tests.into_iter().map(|test| test.name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn collects_every_error_of_a_file() {
+        let (_, errors) = parse_text(
+            "errors",
+            r#"
+[[tests]]
+name = "valid"
+command = ["true"]
+
+[[tests]]
+name = "invalid"
+command = ["true"]
+timeout = "soon"
+env = [{ name = "A", value = 1 }]
+
+[[tests]]
+name = "without command"
+"#,
+        );
+
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| {
+                (
+                    error.key_path.to_string(),
+                    error.line,
+                    error.kind.to_string(),
+                )
+            })
+            .collect();
+        let expected = vec![
+            ("tests[1].env[0].value", 10, "must be a string"),
+            (
+                "tests[1].timeout",
+                9,
+                "invalid duration: expected number at 0",
+            ),
+            ("tests[2].command", 12, "missing"),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(key_path, line, message)| {
+                (key_path.to_string(), Some(line), message.to_string())
+            })
+            .collect();
+        assert_eq!(errors, expected);
+    }
+
+    #[test]
+    fn reports_a_parse_error_at_its_line() {
+        let (_, errors) = parse_text("parse-error", "[[tests]]\nname = \"unterminated\n");
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, Some(2));
+        match *errors[0].kind {
+            ConfigErrorKind::Parse(_) => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn excludes_and_includes_combinations() {
+        let names = test_names(
+            "matrix",
+            r#"
+[[tests]]
+name = "{{os}}-{{toolchain}}"
+command = ["true"]
+[tests.variables]
+os = ["linux", "mac"]
+toolchain = ["stable", "nightly"]
+[[tests.matrix.exclude]]
+os = "mac"
+toolchain = "nightly"
+[[tests.matrix.exclude]]
+os = "linux"
+[[tests.matrix.include]]
+os = "windows"
+toolchain = "stable"
+arch = "x86"
+"#,
+        );
+
+        assert_eq!(names, vec!["mac-stable", "windows-stable"]);
+    }
+
+    #[test]
+    fn excludes_and_includes_file_variables() {
+        let names = test_names(
+            "matrix-file-variables",
+            r#"
+[variables]
+platform = "unix"
+
+[[tests]]
+name = "{{platform}}-{{os}}"
+command = ["true"]
+[tests.variables]
+os = ["linux", "mac"]
+[[tests.matrix.exclude]]
+platform = "unix"
+os = "mac"
+[[tests.matrix.include]]
+os = "bsd"
+"#,
+        );
+
+        assert_eq!(names, vec!["unix-bsd", "unix-linux"]);
+    }
+
+    #[test]
+    fn rejects_an_exclude_of_an_unknown_variable() {
+        let (_, errors) = parse_text(
+            "matrix-unknown",
+            r#"
+[[tests]]
+name = "{{os}}"
+command = ["true"]
+[tests.variables]
+os = ["linux", "mac"]
+[[tests.matrix.exclude]]
+arch = "x86"
+"#,
+        );
+
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.key_path.to_string(), error.kind.to_string()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![(
+                "tests[0].matrix.exclude[0].arch".to_string(),
+                "not a variable of the test".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_the_file_variables_with_several_values() {
+        let (_, errors) = parse_text(
+            "file-variables",
+            r#"
+[variables]
+toolchain = ["stable", "nightly"]
+example = { glob = "*.rs" }
+target = "x86"
+"#,
+        );
+
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.key_path.to_string(), error.line))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("variables.example".to_string(), Some(4)),
+                ("variables.toolchain".to_string(), Some(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn uses_the_default_timeout() {
+        let (result, errors) = parse_text(
+            "default-timeout",
+            r#"
+[defaults]
+timeout = "1m"
+timeout_grace = "5s"
+
+[[tests]]
+name = "default"
+command = ["true"]
+
+[[tests]]
+name = "own"
+command = ["true"]
+timeout = "2m"
+"#,
+        );
+
+        assert!(errors.is_empty());
+        let timeouts: Vec<_> = result
+            .tests
+            .iter()
+            .map(|test| (test.timeout, test.timeout_grace))
+            .collect();
+        assert_eq!(
+            timeouts,
+            vec![
+                (Some(Duration::from_secs(60)), Duration::from_secs(5)),
+                (Some(Duration::from_secs(120)), Duration::from_secs(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_the_templates_into_the_tests() {
+        let (result, errors) = parse_text(
+            "templates",
+            r#"
+[templates.base]
+command = ["cargo", "test"]
+timeout = "20m"
+tags = ["base"]
+env = [{ name = "A", value = "base" }, { name = "B", value = "base" }]
+[templates.base.variables]
+toolchain = ["stable", "beta"]
+features = ["x"]
+[[templates.base.matrix.exclude]]
+toolchain = "beta"
+
+[templates.child]
+extends = "base"
+env = [{ name = "B", value = "child" }]
+[templates.child.variables]
+features = ["y", "z"]
+
+[[tests]]
+name = "{{toolchain}}-{{features}}"
+extends = "child"
+args = ["--release"]
+tags = ["test"]
+[[tests.matrix.exclude]]
+features = "z"
+"#,
+        );
+
+        assert!(errors.is_empty());
+        assert_eq!(result.tests.len(), 1);
+        let test = &result.tests[0];
+        assert_eq!(test.name, "stable-y");
+        assert_eq!(test.command, vec!["cargo", "test", "--release"]);
+        assert_eq!(test.timeout, Some(Duration::from_secs(20 * 60)));
+        assert_eq!(test.tags, vec!["test"]);
+        let env: Vec<_> = test
+            .env
+            .iter()
+            .map(|(name, value)| (&**name, &**value))
+            .collect();
+        assert_eq!(env, vec![("B", "child"), ("A", "base")]);
+    }
+
+    #[test]
+    fn rejects_unknown_and_cyclic_templates() {
+        let (_, errors) = parse_text(
+            "template-errors",
+            r#"
+[templates.a]
+extends = "b"
+
+[templates.b]
+extends = "a"
+
+[[tests]]
+name = "unknown"
+command = ["true"]
+extends = "missing"
+"#,
+        );
+
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.key_path.to_string(), error.kind.to_string()))
+            .collect();
+        let expected = vec![
+            // The error is at the key closing the cycle.
+            (
+                "templates.b.extends",
+                "the templates extend each other: a -> b -> a",
+            ),
+            (
+                "templates.a.extends",
+                "the templates extend each other: b -> a -> b",
+            ),
+            ("tests[0].extends", "unknown template \"missing\""),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(key_path, message)| (key_path.to_string(), message.to_string()))
+            .collect();
+        assert_eq!(errors, expected);
+    }
 }
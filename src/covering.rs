@@ -0,0 +1,260 @@
+use std::cmp;
+use std::collections::BTreeSet;
+
+/// The number of candidate rows generated to choose each row of a covering array.
+const CANDIDATES: usize = 20;
+
+/// A combination of `strength` factors with a level for each, sorted by factor.
+type Tuple = Vec<(usize, usize)>;
+
+/// A small deterministic pseudo-random number generator (SplitMix64).
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number between 0 and `n` excluded.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn shuffle<T>(&mut self, values: &mut [T]) {
+        for i in (1..values.len()).rev() {
+            let j = self.below(i + 1);
+            values.swap(i, j);
+        }
+    }
+}
+
+/// Every subset of `size` elements of `0..n`, in lexicographic order.
+fn subsets(n: usize, size: usize) -> Vec<Vec<usize>> {
+    fn extend(n: usize, size: usize, current: &mut Vec<usize>, subsets: &mut Vec<Vec<usize>>) {
+        if current.len() == size {
+            subsets.push(current.clone());
+            return;
+        }
+
+        let start = current.last().map_or(0, |&last| last + 1);
+        for i in start..n {
+            current.push(i);
+            extend(n, size, current, subsets);
+            current.pop();
+        }
+    }
+
+    let mut subsets = vec![];
+    extend(n, size, &mut vec![], &mut subsets);
+    subsets
+}
+
+/// Every tuple of levels of the factors of `factors`.
+fn tuples(sizes: &[usize], factors: &[usize]) -> Vec<Tuple> {
+    let mut tuples = vec![vec![]];
+
+    for &factor in factors {
+        tuples = tuples
+            .into_iter()
+            .flat_map(|tuple: Tuple| {
+                (0..sizes[factor]).map(move |level| {
+                    let mut tuple = tuple.clone();
+                    tuple.push((factor, level));
+                    tuple
+                })
+            })
+            .collect();
+    }
+
+    tuples
+}
+
+fn tuple_of(row: &[usize], factors: &[usize]) -> Tuple {
+    factors.iter().map(|&factor| (factor, row[factor])).collect()
+}
+
+/// Generate a row covering as many uncovered tuples as possible: it starts from an uncovered
+/// tuple and the level of each other factor, taken in a random order, is the one covering the most
+/// tuples with the factors that already have a level.
+fn candidate(
+    sizes: &[usize],
+    factor_sets: &[Vec<usize>],
+    uncovered: &BTreeSet<Tuple>,
+    rng: &mut Rng,
+) -> Vec<usize> {
+    let mut row = vec![0; sizes.len()];
+    let mut assigned = vec![false; sizes.len()];
+
+    let start = uncovered.iter().nth(rng.below(uncovered.len())).unwrap();
+    for &(factor, level) in start {
+        row[factor] = level;
+        assigned[factor] = true;
+    }
+
+    let mut order: Vec<_> = (0..sizes.len()).filter(|&factor| !assigned[factor]).collect();
+    rng.shuffle(&mut order);
+
+    for factor in order {
+        assigned[factor] = true;
+        let mut best_levels = vec![];
+        let mut best_count = 0;
+
+        for level in 0..sizes[factor] {
+            row[factor] = level;
+            let count = factor_sets
+                .iter()
+                .filter(|factors| {
+                    factors.contains(&factor) && factors.iter().all(|&other| assigned[other])
+                })
+                .filter(|factors| uncovered.contains(&tuple_of(&row, factors)))
+                .count();
+
+            if count > best_count || best_levels.is_empty() {
+                best_levels.clear();
+                best_count = count;
+            }
+            if count == best_count {
+                best_levels.push(level);
+            }
+        }
+
+        row[factor] = best_levels[rng.below(best_levels.len())];
+    }
+
+    row
+}
+
+/// Generate a covering array of the given strength: rows giving a level to each factor, such
+/// that every combination of levels of any `strength` factors is in at least one row. `sizes`
+/// are the number of levels of each factor.
+///
+/// The array is built greedily, so it is small but not always the smallest. It only depends on
+/// `seed`. The rows are sorted.
+pub fn covering_array(sizes: &[usize], strength: usize, seed: u64) -> Vec<Vec<usize>> {
+    if sizes.contains(&0) {
+        return vec![];
+    }
+
+    let factor_sets = subsets(sizes.len(), cmp::min(strength, sizes.len()));
+    let mut uncovered: BTreeSet<Tuple> = factor_sets
+        .iter()
+        .flat_map(|factors| tuples(sizes, factors))
+        .collect();
+    let mut rng = Rng(seed);
+    let mut rows = vec![];
+
+    while !uncovered.is_empty() {
+        let mut best: Option<(usize, Vec<usize>)> = None;
+
+        for _ in 0..CANDIDATES {
+            let row = candidate(sizes, &factor_sets, &uncovered, &mut rng);
+            let covered = factor_sets
+                .iter()
+                .filter(|factors| uncovered.contains(&tuple_of(&row, factors)))
+                .count();
+
+            let is_better = match best {
+                Some((best_covered, _)) => covered > best_covered,
+                None => true,
+            };
+            if is_better {
+                best = Some((covered, row));
+            }
+        }
+
+        let (_, row) = best.unwrap();
+        for factors in &factor_sets {
+            uncovered.remove(&tuple_of(&row, factors));
+        }
+        rows.push(row);
+    }
+
+    rows.sort();
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that every combination of levels of any `strength` factors is in a row.
+    fn assert_covers(sizes: &[usize], strength: usize, rows: &[Vec<usize>]) {
+        for row in rows {
+            assert_eq!(row.len(), sizes.len());
+            for (&level, &size) in row.iter().zip(sizes) {
+                assert!(level < size, "invalid row {:?} for sizes {:?}", row, sizes);
+            }
+        }
+
+        for factors in subsets(sizes.len(), cmp::min(strength, sizes.len())) {
+            for tuple in tuples(sizes, &factors) {
+                assert!(
+                    rows.iter().any(|row| tuple_of(row, &factors) == tuple),
+                    "{:?} is not covered by {:?}",
+                    tuple,
+                    rows
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn covers_every_pair() {
+        for sizes in &[vec![2, 2, 2], vec![3, 3, 3, 3], vec![2, 3, 4, 5], vec![4, 1, 3, 1, 2]] {
+            let rows = covering_array(sizes, 2, 0);
+            assert_covers(sizes, 2, &rows);
+        }
+    }
+
+    #[test]
+    fn covers_every_triple() {
+        for sizes in &[vec![2, 2, 2, 2, 2], vec![3, 2, 4, 2], vec![1, 3, 2, 1, 2]] {
+            let rows = covering_array(sizes, 3, 0);
+            assert_covers(sizes, 3, &rows);
+        }
+    }
+
+    #[test]
+    fn is_smaller_than_the_cartesian_product() {
+        let rows = covering_array(&[3, 3, 3, 3], 2, 0);
+        assert!(rows.len() >= 9 && rows.len() < 81, "{} rows", rows.len());
+    }
+
+    #[test]
+    fn has_one_row_for_factors_of_one_level() {
+        assert_eq!(covering_array(&[1, 1, 1], 2, 0), vec![vec![0, 0, 0]]);
+    }
+
+    #[test]
+    fn is_the_cartesian_product_with_a_strength_above_the_factors() {
+        let rows = covering_array(&[2, 3], 3, 0);
+        let expected = vec![
+            vec![0, 0],
+            vec![0, 1],
+            vec![0, 2],
+            vec![1, 0],
+            vec![1, 1],
+            vec![1, 2],
+        ];
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn is_empty_with_a_factor_without_levels() {
+        assert!(covering_array(&[2, 0, 3], 2, 0).is_empty());
+    }
+
+    #[test]
+    fn only_depends_on_the_seed() {
+        let sizes = [3, 4, 2, 5, 3, 2];
+        for seed in 0..5 {
+            let rows = covering_array(&sizes, 2, seed);
+            assert_eq!(rows, covering_array(&sizes, 2, seed));
+            assert_covers(&sizes, 2, &rows);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use glob::{GlobError, PatternError};
+use humantime::DurationError;
+use liquid;
+use regex::{self, Regex};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use toml;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyPart {
+    Key(String),
+    Index(usize),
+}
+
+/// The path of a value inside a TOML document, like `tests[2].env[0].name`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyPath(pub Vec<KeyPart>);
+
+impl KeyPath {
+    pub fn root() -> KeyPath {
+        KeyPath(vec![])
+    }
+
+    pub fn key<S: Into<String>>(&self, key: S) -> KeyPath {
+        let mut parts = self.0.clone();
+        parts.push(KeyPart::Key(key.into()));
+        KeyPath(parts)
+    }
+
+    pub fn index(&self, index: usize) -> KeyPath {
+        let mut parts = self.0.clone();
+        parts.push(KeyPart::Index(index));
+        KeyPath(parts)
+    }
+
+    fn parent(&self) -> Option<KeyPath> {
+        if self.0.is_empty() {
+            None
+        } else {
+            Some(KeyPath(self.0[..self.0.len() - 1].to_vec()))
+        }
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, part) in self.0.iter().enumerate() {
+            match *part {
+                KeyPart::Key(ref key) if i == 0 => write!(f, "{}", key)?,
+                KeyPart::Key(ref key) => write!(f, ".{}", key)?,
+                KeyPart::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub enum ConfigErrorKind {
+    NotFound,
+    Io(io::Error),
+    /// The command generating the values of a variable couldn't be run.
+    Command(io::Error),
+    CommandFailed(ExitStatus),
+    Parse(toml::de::Error),
+    Missing,
+    /// The value doesn't have the expected type. Contains a description of the expected type.
+    InvalidType(&'static str),
+    InvalidDuration(DurationError),
+    InvalidRegex(regex::Error),
+    /// The key is not the name of a variable of the test.
+    UnknownVariable,
+    DuplicateVariable,
+    UnknownTemplate(String),
+    /// The templates extend each other. Contains the names of the templates of the cycle.
+    TemplateCycle(Vec<String>),
+    /// The variable of a zip doesn't have as many values as the first one. Contains the name of
+    /// the first variable and its number of values.
+    ZipLength(String, usize),
+    Template(liquid::Error),
+    Render(liquid::Error),
+    InvalidGlob(PatternError),
+    Glob(GlobError),
+    /// The working directory of a test doesn't exist.
+    MissingDirectory(PathBuf),
+    /// The key cannot be used with the other key.
+    Conflict(&'static str),
+}
+
+impl fmt::Display for ConfigErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigErrorKind::NotFound => write!(f, "not found"),
+            ConfigErrorKind::Io(ref error) => write!(f, "cannot read the file: {}", error),
+            ConfigErrorKind::Command(ref error) => write!(f, "cannot run the command: {}", error),
+            ConfigErrorKind::CommandFailed(status) => write!(f, "the command failed: {}", status),
+            ConfigErrorKind::Parse(ref error) => write!(f, "{}", error),
+            ConfigErrorKind::Missing => write!(f, "missing"),
+            ConfigErrorKind::InvalidType(expected) => write!(f, "must be {}", expected),
+            ConfigErrorKind::InvalidDuration(ref error) => {
+                write!(f, "invalid duration: {}", error)
+            }
+            ConfigErrorKind::UnknownVariable => write!(f, "not a variable of the test"),
+            ConfigErrorKind::DuplicateVariable => {
+                write!(f, "already a variable of the test")
+            }
+            ConfigErrorKind::UnknownTemplate(ref name) => write!(f, "unknown template \"{}\"", name),
+            ConfigErrorKind::TemplateCycle(ref names) => write!(
+                f,
+                "the templates extend each other: {}",
+                names.join(" -> ")
+            ),
+            ConfigErrorKind::ZipLength(ref first, length) => write!(
+                f,
+                "must have as many values as `{}` ({}) to be zipped with it",
+                first, length
+            ),
+            ConfigErrorKind::InvalidRegex(ref error) => write!(f, "invalid regex: {}", error),
+            ConfigErrorKind::Template(ref error) => write!(f, "invalid template: {}", error),
+            ConfigErrorKind::Render(ref error) => {
+                write!(f, "cannot render the template: {}", error)
+            }
+            ConfigErrorKind::InvalidGlob(ref error) => write!(f, "invalid glob pattern: {}", error),
+            ConfigErrorKind::Glob(ref error) => write!(f, "{}", error),
+            ConfigErrorKind::MissingDirectory(ref path) => {
+                write!(f, "the directory {} doesn't exist", path.display())
+            }
+            ConfigErrorKind::Conflict(other) => write!(f, "cannot be used with {}", other),
+        }
+    }
+}
+
+/// An error in a configuration file.
+///
+/// The errors are first created with only a key path and a kind. The configuration file, the line
+/// and the test are filled in by the caller that knows them.
+pub struct ConfigError {
+    pub config_file: PathBuf,
+    pub line: Option<usize>,
+    pub test_index: Option<usize>,
+    pub test_name: Option<String>,
+    pub key_path: KeyPath,
+    /// Boxed to keep the results returning configuration errors small.
+    pub kind: Box<ConfigErrorKind>,
+}
+
+impl ConfigError {
+    pub fn new(key_path: KeyPath, kind: ConfigErrorKind) -> ConfigError {
+        ConfigError {
+            config_file: PathBuf::new(),
+            line: None,
+            test_index: None,
+            test_name: None,
+            key_path,
+            kind: Box::new(kind),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.config_file.display())?;
+
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+
+        if !self.key_path.0.is_empty() {
+            write!(f, ": {}", self.key_path)?;
+        }
+
+        if let Some(ref test_name) = self.test_name {
+            write!(f, " (test \"{}\")", test_name)?;
+        }
+
+        write!(f, ": {}", self.kind)
+    }
+}
+
+/// The line of the table headers and keys of a TOML document, used to show where an error is.
+///
+/// This only looks at the table headers and at the lines starting with a key, so the keys of
+/// inline tables are located at the line of the inline table.
+pub struct SourceLines {
+    lines: HashMap<String, usize>,
+}
+
+fn header_parts(header: &str) -> Vec<String> {
+    header
+        .split('.')
+        .map(|part| part.trim().trim_matches('"').trim_matches('\'').to_string())
+        .collect()
+}
+
+impl SourceLines {
+    pub fn new(text: &str) -> SourceLines {
+        let array_header = Regex::new(r"^\[\[([^\]]+)\]\]\s*(#.*)?$").unwrap();
+        let table_header = Regex::new(r"^\[([^\]]+)\]\s*(#.*)?$").unwrap();
+        let key = Regex::new(r#"^([A-Za-z0-9_-]+|"[^"]*"|'[^']*')\s*="#).unwrap();
+
+        let mut lines = HashMap::new();
+        // The number of elements of each array of tables, by path.
+        let mut array_lengths: HashMap<String, usize> = HashMap::new();
+        let mut current_table = KeyPath::root();
+
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let line = line.trim();
+
+            let (header, is_array) = if let Some(captures) = array_header.captures(line) {
+                (captures.get(1).unwrap().as_str(), true)
+            } else if let Some(captures) = table_header.captures(line) {
+                (captures.get(1).unwrap().as_str(), false)
+            } else {
+                if let Some(captures) = key.captures(line) {
+                    let key = captures.get(1).unwrap().as_str();
+                    let key = key.trim_matches('"').trim_matches('\'');
+                    lines
+                        .entry(current_table.key(key).to_string())
+                        .or_insert(number);
+                }
+                continue;
+            };
+
+            let parts = header_parts(header);
+            let mut path = KeyPath::root();
+
+            for (i, part) in parts.iter().enumerate() {
+                path = path.key(&**part);
+                let path_string = path.to_string();
+
+                if is_array && i == parts.len() - 1 {
+                    let length = array_lengths.entry(path_string).or_insert(0);
+                    path = path.index(*length);
+                    *length += 1;
+                } else if let Some(&length) = array_lengths.get(&path_string) {
+                    // A sub-table of the last element of an array of tables.
+                    path = path.index(length - 1);
+                }
+            }
+
+            lines.entry(path.to_string()).or_insert(number);
+            current_table = path;
+        }
+
+        SourceLines { lines }
+    }
+
+    /// The line of the key, or of its closest parent with a known line.
+    pub fn find(&self, key_path: &KeyPath) -> Option<usize> {
+        let mut key_path = key_path.clone();
+
+        loop {
+            if let Some(&line) = self.lines.get(&key_path.to_string()) {
+                return Some(line);
+            }
+
+            key_path = key_path.parent()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[defaults]
+timeout = "1s"
+
+[[tests]]
+name = "first"
+env = { A = "1", B = "2" }
+
+[[tests]]
+name = "second" # a comment
+
+[tests.variables]
+x = [1, 2]
+
+[[tests.checks]]
+"quoted key" = true
+
+[[tests.checks]]
+command = "true"
+"#;
+
+    fn path(parts: &[&str]) -> KeyPath {
+        KeyPath(
+            parts
+                .iter()
+                .map(|part| match part.parse() {
+                    Ok(index) => KeyPart::Index(index),
+                    Err(_) => KeyPart::Key(part.to_string()),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn key_path_display() {
+        assert_eq!(path(&[]).to_string(), "");
+        assert_eq!(path(&["tests", "2", "env", "0", "name"]).to_string(), "tests[2].env[0].name");
+    }
+
+    #[test]
+    fn finds_tables_and_keys() {
+        let lines = SourceLines::new(CONFIG);
+        assert_eq!(lines.find(&path(&["defaults"])), Some(2));
+        assert_eq!(lines.find(&path(&["defaults", "timeout"])), Some(3));
+    }
+
+    #[test]
+    fn indexes_arrays_of_tables() {
+        let lines = SourceLines::new(CONFIG);
+        assert_eq!(lines.find(&path(&["tests", "0"])), Some(5));
+        assert_eq!(lines.find(&path(&["tests", "0", "name"])), Some(6));
+        assert_eq!(lines.find(&path(&["tests", "1"])), Some(9));
+        assert_eq!(lines.find(&path(&["tests", "1", "name"])), Some(10));
+    }
+
+    #[test]
+    fn indexes_sub_tables_of_arrays_of_tables() {
+        let lines = SourceLines::new(CONFIG);
+        assert_eq!(lines.find(&path(&["tests", "1", "variables"])), Some(12));
+        assert_eq!(lines.find(&path(&["tests", "1", "variables", "x"])), Some(13));
+        assert_eq!(lines.find(&path(&["tests", "1", "checks", "0"])), Some(15));
+        assert_eq!(lines.find(&path(&["tests", "1", "checks", "0", "quoted key"])), Some(16));
+        assert_eq!(lines.find(&path(&["tests", "1", "checks", "1", "command"])), Some(19));
+    }
+
+    #[test]
+    fn finds_inline_tables_at_their_key() {
+        let lines = SourceLines::new(CONFIG);
+        assert_eq!(lines.find(&path(&["tests", "0", "env"])), Some(7));
+        assert_eq!(lines.find(&path(&["tests", "0", "env", "B"])), Some(7));
+    }
+
+    #[test]
+    fn falls_back_to_the_parent_key() {
+        let lines = SourceLines::new(CONFIG);
+        assert_eq!(lines.find(&path(&["tests", "0", "command"])), Some(5));
+        assert_eq!(lines.find(&path(&["tests", "1", "variables", "x", "1"])), Some(13));
+        assert_eq!(lines.find(&path(&["tests", "2", "name"])), None);
+        assert_eq!(lines.find(&path(&["includes"])), None);
+    }
+}
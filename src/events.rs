@@ -0,0 +1,232 @@
+use config::RunConfigResult;
+use error::ConfigError;
+use runner::HookKind;
+use serde_json::Value;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::Duration;
+use test::{Outcome, Test, TestRun};
+
+/// How the progress and the results of a run are reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Colored messages on stderr.
+    Human,
+    /// Newline-delimited JSON events on stdout.
+    Json,
+}
+
+fn emit(event: &Value) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = writeln!(stdout, "{}", event);
+    let _ = stdout.flush();
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+fn config_file<T1, T2, T3>(test: &Test<T1, T2, T3>) -> Value {
+    match test.config_file {
+        Some(ref config_file) => json!(config_file.to_string_lossy()),
+        None => Value::Null,
+    }
+}
+
+pub fn outcome_name(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Success => "success",
+        Outcome::Failure => "failure",
+        Outcome::TimedOut => "timed_out",
+        Outcome::Skipped => "skipped",
+        Outcome::Error => "error",
+        Outcome::Flaky => "flaky",
+    }
+}
+
+pub fn run_started(config_file: &Path, jobs: usize) {
+    emit(&json!({
+        "event": "run_started",
+        "config_file": config_file.to_string_lossy(),
+        "jobs": jobs,
+    }));
+}
+
+/// Sent while the configuration is loaded, before any test is run.
+pub fn include_loading(config_file: &Path) {
+    emit(&json!({
+        "event": "include_loading",
+        "config_file": config_file.to_string_lossy(),
+    }));
+}
+
+pub fn include_loaded(config_file: &Path) {
+    emit(&json!({
+        "event": "include_loaded",
+        "config_file": config_file.to_string_lossy(),
+    }));
+}
+
+pub fn test_discovered(test: &Test<String, String, String>, ignored: bool) {
+    emit(&json!({
+        "event": "test_discovered",
+        "name": test.name,
+        "config_file": config_file(test),
+        "command": test.to_string(),
+        "cwd": test.cwd.as_ref().map(|cwd| cwd.to_string_lossy()),
+        "tags": test.tags,
+        "ignored": ignored,
+    }));
+}
+
+pub fn test_started(test: &Test<String, String, String>) {
+    emit(&json!({
+        "event": "test_started",
+        "name": test.name,
+        "config_file": config_file(test),
+    }));
+}
+
+/// The output is only part of the event if `include_output` is true.
+pub fn test_finished(test: &Test<String, String, String>, test_run: &TestRun, include_output: bool) {
+    let mut event = json!({
+        "event": "test_finished",
+        "name": test.name,
+        "config_file": config_file(test),
+        "status": outcome_name(test_run.outcome()),
+        "exit_code": test_run.exit_code(),
+        "signal": test_run.signal(),
+        "duration": seconds(test_run.duration),
+        "attempts": test_run.attempts,
+        "message": test_run.failure_message(),
+    });
+
+    if include_output {
+        event["output"] = json!(String::from_utf8_lossy(&test_run.output));
+    }
+
+    emit(&event);
+}
+
+pub fn hook_started(kind: HookKind, hook: &Test<String, String, String>) {
+    emit(&json!({
+        "event": "hook_started",
+        "kind": kind.name(),
+        "name": hook.name,
+        "config_file": config_file(hook),
+        "command": hook.to_string(),
+    }));
+}
+
+/// The output is only part of the event if `include_output` is true.
+pub fn hook_finished(
+    kind: HookKind,
+    hook: &Test<String, String, String>,
+    test_run: &TestRun,
+    include_output: bool,
+) {
+    let mut event = json!({
+        "event": "hook_finished",
+        "kind": kind.name(),
+        "name": hook.name,
+        "config_file": config_file(hook),
+        "status": outcome_name(test_run.outcome()),
+        "exit_code": test_run.exit_code(),
+        "signal": test_run.signal(),
+        "duration": seconds(test_run.duration),
+        "message": test_run.failure_message(),
+    });
+
+    if include_output {
+        event["output"] = json!(String::from_utf8_lossy(&test_run.output));
+    }
+
+    emit(&event);
+}
+
+pub fn service_starting(service: &Test<String, String, String>) {
+    emit(&json!({
+        "event": "service_starting",
+        "name": service.name,
+        "config_file": config_file(service),
+        "command": service.to_string(),
+    }));
+}
+
+/// `message` tells why the service couldn't be started or wasn't ready.
+pub fn service_started(
+    service: &Test<String, String, String>,
+    message: Option<&str>,
+    duration: Duration,
+) {
+    emit(&json!({
+        "event": "service_started",
+        "name": service.name,
+        "config_file": config_file(service),
+        "status": if message.is_some() { "error" } else { "ready" },
+        "duration": seconds(duration),
+        "message": message,
+    }));
+}
+
+/// `message` tells why the service failed if it exited before being stopped. The log is only
+/// part of the event if it is given.
+pub fn service_stopped(
+    service: &Test<String, String, String>,
+    message: &Option<String>,
+    log: Option<&[u8]>,
+) {
+    let mut event = json!({
+        "event": "service_stopped",
+        "name": service.name,
+        "config_file": config_file(service),
+        "message": message,
+    });
+
+    if let Some(log) = log {
+        event["log"] = json!(String::from_utf8_lossy(log));
+    }
+
+    emit(&event);
+}
+
+pub fn config_error(error: &ConfigError) {
+    emit(&json!({
+        "event": "config_error",
+        "config_file": error.config_file.to_string_lossy(),
+        "line": error.line,
+        "test_index": error.test_index,
+        "test_name": error.test_name,
+        "key": error.key_path.to_string(),
+        "message": error.kind.to_string(),
+    }));
+}
+
+pub fn run_finished(result: &RunConfigResult, success: bool, duration: Duration) {
+    let count = |outcome| {
+        result
+            .results
+            .iter()
+            .filter(|result| result.outcome == outcome)
+            .count()
+    };
+
+    emit(&json!({
+        "event": "run_finished",
+        "success": success,
+        "total": result.results.len(),
+        "successes": count(Outcome::Success),
+        "failures": count(Outcome::Failure),
+        "timed_out": count(Outcome::TimedOut),
+        "skipped": count(Outcome::Skipped),
+        "errors": count(Outcome::Error),
+        "flaky": count(Outcome::Flaky),
+        "ignored": result.ignored.len(),
+        "not_run": result.not_run.len(),
+        "hook_failures": result.hook_failures.len(),
+        "interrupted": result.interrupted,
+        "duration": seconds(duration),
+    }));
+}
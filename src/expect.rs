@@ -0,0 +1,293 @@
+use diff;
+use regex::Regex;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+/// The number of unchanged lines shown around the changed lines of a diff.
+const DIFF_CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stream::Stdout => write!(f, "stdout"),
+            Stream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// How the expected value is compared to the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchKind {
+    Exact,
+    Contains,
+    Regex,
+    /// The output must be exactly the content of a file.
+    File,
+}
+
+#[derive(Clone, Debug)]
+pub enum Matcher {
+    Exact(String),
+    Contains(String),
+    Regex(Regex),
+    File(PathBuf),
+}
+
+/// An assertion on the stdout or the stderr of a test.
+#[derive(Clone, Debug)]
+pub struct Expectation {
+    pub stream: Stream,
+    pub matcher: Matcher,
+}
+
+/// The stdout and the stderr of a test, captured separately.
+#[derive(Default)]
+pub struct Streams {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Expectation {
+    /// Check the output of a test. Return a description of the mismatch, with a diff for exact
+    /// matches, if the output isn't the expected one.
+    pub fn check(&self, streams: &Streams) -> Option<String> {
+        let output = match self.stream {
+            Stream::Stdout => &streams.stdout,
+            Stream::Stderr => &streams.stderr,
+        };
+        let output = String::from_utf8_lossy(output);
+
+        match self.matcher {
+            Matcher::Exact(ref expected) => self.check_exact(expected, &output, None),
+            Matcher::Contains(ref expected) => if output.contains(&**expected) {
+                None
+            } else {
+                Some(format!("{} doesn't contain {:?}", self.stream, expected))
+            },
+            Matcher::Regex(ref regex) => if regex.is_match(&output) {
+                None
+            } else {
+                Some(format!("{} doesn't match the regex {:?}", self.stream, regex.as_str()))
+            },
+            Matcher::File(ref path) => {
+                let mut expected = String::new();
+                match File::open(path).and_then(|mut file| file.read_to_string(&mut expected)) {
+                    Ok(_) => self.check_exact(&expected, &output, Some(path)),
+                    Err(error) => Some(format!(
+                        "cannot read the expected {} from {}: {}",
+                        self.stream,
+                        path.display(),
+                        error
+                    )),
+                }
+            }
+        }
+    }
+
+    fn check_exact(&self, expected: &str, output: &str, file: Option<&PathBuf>) -> Option<String> {
+        if expected == output {
+            return None;
+        }
+
+        let source = match file {
+            Some(file) => format!(" of {}", file.display()),
+            None => String::new(),
+        };
+
+        Some(format!(
+            "{} isn't the expected output{} (-expected +actual):\n{}",
+            self.stream,
+            source,
+            format_diff(expected, output)
+        ))
+    }
+}
+
+/// A line diff showing the changed lines with a few lines of context.
+fn format_diff(expected: &str, actual: &str) -> String {
+    let mut lines: Vec<_> = diff::lines(expected, actual)
+        .into_iter()
+        .map(|line| match line {
+            diff::Result::Left(line) => ('-', line),
+            diff::Result::Both(line, _) => (' ', line),
+            diff::Result::Right(line) => ('+', line),
+        })
+        .collect();
+
+    // The diff ends with an empty line if either text ends with a newline.
+    if expected.ends_with('\n') || actual.ends_with('\n') {
+        lines.pop();
+    }
+
+    let changed: Vec<_> = lines
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(kind, _))| kind != ' ')
+        .map(|(i, _)| i)
+        .collect();
+    // When only the newline at the end differs, every line is shown.
+    let is_shown = |i: usize| {
+        changed.is_empty() || changed
+            .iter()
+            .any(|&changed| changed <= i + DIFF_CONTEXT && i <= changed + DIFF_CONTEXT)
+    };
+
+    let mut formatted = String::new();
+    let mut skipped = false;
+
+    for (i, &(kind, line)) in lines.iter().enumerate() {
+        if is_shown(i) {
+            formatted.push_str(&format!("{}{}\n", kind, line));
+            skipped = false;
+        } else if !skipped {
+            formatted.push_str("...\n");
+            skipped = true;
+        }
+    }
+
+    // A missing or extra newline at the end of the output is otherwise invisible.
+    if expected.ends_with('\n') != actual.ends_with('\n') {
+        formatted.push_str("(the newline at the end differs)\n");
+    }
+
+    formatted.pop();
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn check(stream: Stream, matcher: Matcher, stdout: &str, stderr: &str) -> Option<String> {
+        let expectation = Expectation { stream, matcher };
+        expectation.check(&Streams {
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+
+    fn exact(expected: &str, stdout: &str) -> Option<String> {
+        check(
+            Stream::Stdout,
+            Matcher::Exact(expected.to_string()),
+            stdout,
+            "",
+        )
+    }
+
+    #[test]
+    fn checks_an_exact_output() {
+        assert_eq!(exact("a\nb\n", "a\nb\n"), None);
+        assert_eq!(
+            exact("a\nb\n", "a\nc\n"),
+            Some("stdout isn't the expected output (-expected +actual):\n a\n-b\n+c".to_string())
+        );
+    }
+
+    #[test]
+    fn checks_the_stream_of_the_expectation() {
+        let matcher = Matcher::Exact("error\n".to_string());
+        assert_eq!(check(Stream::Stderr, matcher.clone(), "", "error\n"), None);
+        assert_eq!(
+            check(Stream::Stderr, matcher, "error\n", ""),
+            Some(
+                "stderr isn't the expected output (-expected +actual):\n-error\n\
+                 (the newline at the end differs)"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn checks_that_the_output_contains_a_string() {
+        let matcher = Matcher::Contains("ok".to_string());
+        assert_eq!(
+            check(Stream::Stdout, matcher.clone(), "tests ok\n", ""),
+            None
+        );
+        assert_eq!(
+            check(Stream::Stdout, matcher, "tests failed\n", "ok"),
+            Some("stdout doesn't contain \"ok\"".to_string())
+        );
+    }
+
+    #[test]
+    fn checks_that_the_output_matches_a_regex() {
+        let matcher = Matcher::Regex(Regex::new(r"^version \d+").unwrap());
+        assert_eq!(
+            check(Stream::Stdout, matcher.clone(), "version 2\n", ""),
+            None
+        );
+        assert_eq!(
+            check(Stream::Stdout, matcher, "v2\n", ""),
+            Some("stdout doesn't match the regex \"^version \\\\d+\"".to_string())
+        );
+    }
+
+    #[test]
+    fn checks_an_output_against_a_file() {
+        let path = env::temp_dir().join(format!("multitest-expected-{}.txt", process::id()));
+        fs::write(&path, "a\nb\n").unwrap();
+        let matcher = Matcher::File(path.clone());
+
+        let matching = check(Stream::Stdout, matcher.clone(), "a\nb\n", "");
+        let mismatch = check(Stream::Stdout, matcher.clone(), "a\n", "");
+        fs::remove_file(&path).unwrap();
+        let missing = check(Stream::Stdout, matcher, "a\n", "").unwrap();
+
+        assert_eq!(matching, None);
+        assert_eq!(
+            mismatch,
+            Some(format!(
+                "stdout isn't the expected output of {} (-expected +actual):\n a\n-b",
+                path.display()
+            ))
+        );
+        let prefix = format!("cannot read the expected stdout from {}: ", path.display());
+        assert!(missing.starts_with(&prefix), "{}", missing);
+    }
+
+    #[test]
+    fn shows_the_context_of_the_changes() {
+        let expected = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n";
+        let actual = "1\n2\n3\n4\n5\n6\n7\nchanged\n9\n10\n11\n12\n13\n14\nlast\n";
+        assert_eq!(
+            format_diff(expected, actual),
+            "...\n 5\n 6\n 7\n-8\n+changed\n 9\n 10\n 11\n 12\n 13\n 14\n-15\n+last"
+        );
+    }
+
+    #[test]
+    fn cuts_the_lines_between_two_contexts() {
+        let expected = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let actual = "changed\n2\n3\n4\n5\n6\n7\n8\n9\nlast\n";
+        assert_eq!(
+            format_diff(expected, actual),
+            "-1\n+changed\n 2\n 3\n 4\n...\n 7\n 8\n 9\n-10\n+last"
+        );
+    }
+
+    #[test]
+    fn notes_a_different_newline_at_the_end() {
+        assert_eq!(
+            format_diff("a\nb\n", "a\nb"),
+            " a\n b\n(the newline at the end differs)"
+        );
+        assert_eq!(
+            format_diff("a\nb", "a\nc\n"),
+            " a\n-b\n+c\n(the newline at the end differs)"
+        );
+        assert_eq!(format_diff("a\nb", "a\nc"), " a\n-b\n+c");
+    }
+}
@@ -0,0 +1,111 @@
+use regex::{self, Regex};
+use test::Test;
+
+/// The separator between the configuration file and the test name of a filter.
+const PATH_SEPARATOR: &str = "::";
+
+/// A filter on the tests given on the command line. It is a regex matching the test name, or
+/// `path::name` to also match the path of the configuration file defining the test. Since test
+/// names can contain `::` too, a `path::name` filter also matches the names matching all of it.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    config_file: Option<Regex>,
+    name: Regex,
+    /// The whole filter, when it has a path.
+    whole_name: Option<Regex>,
+}
+
+fn name_regex(name: &str, exact: bool) -> Result<Regex, regex::Error> {
+    if exact {
+        Regex::new(&format!("^{}$", regex::escape(name)))
+    } else {
+        Regex::new(name)
+    }
+}
+
+impl Filter {
+    /// With `exact`, the name must be exactly the given one, and the path of the configuration
+    /// file must end with the given path.
+    pub fn new(filter: &str, exact: bool) -> Result<Filter, regex::Error> {
+        let (config_file, name) = match filter.find(PATH_SEPARATOR) {
+            Some(i) => (Some(&filter[..i]), &filter[i + PATH_SEPARATOR.len()..]),
+            None => (None, filter),
+        };
+
+        let config_file = match config_file {
+            Some(config_file) if exact => Some(Regex::new(&format!(
+                "(^|/){}$",
+                regex::escape(config_file)
+            ))?),
+            Some(config_file) => Some(Regex::new(config_file)?),
+            None => None,
+        };
+        let whole_name = match config_file {
+            Some(_) => Some(name_regex(filter, exact)?),
+            None => None,
+        };
+
+        Ok(Filter {
+            config_file,
+            name: name_regex(name, exact)?,
+            whole_name,
+        })
+    }
+
+    pub fn matches(&self, test: &Test<String, String, String>) -> bool {
+        let config_file_matches = match self.config_file {
+            Some(ref regex) => match test.config_file {
+                Some(ref config_file) => regex.is_match(&config_file.to_string_lossy()),
+                None => false,
+            },
+            None => true,
+        };
+
+        let whole_name_matches = match self.whole_name {
+            Some(ref regex) => regex.is_match(&test.name),
+            None => false,
+        };
+
+        (config_file_matches && self.name.is_match(&test.name)) || whole_name_matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test(config_file: &str, name: &str) -> Test<String, String, String> {
+        let mut test = Test::new(name, vec![], false, vec![]);
+        test.config_file = Some(PathBuf::from(config_file));
+        test
+    }
+
+    fn matches(filter: &str, exact: bool, test: &Test<String, String, String>) -> bool {
+        Filter::new(filter, exact).unwrap().matches(test)
+    }
+
+    #[test]
+    fn matches_the_name() {
+        assert!(matches("bui", false, &test("multitest.toml", "build")));
+        assert!(!matches("bui", true, &test("multitest.toml", "build")));
+        assert!(matches("build", true, &test("multitest.toml", "build")));
+    }
+
+    #[test]
+    fn matches_the_path_and_the_name() {
+        let test = test("crates/core/multitest.toml", "build");
+        assert!(matches("core::build", false, &test));
+        assert!(!matches("cli::build", false, &test));
+        assert!(matches("core/multitest.toml::build", true, &test));
+        assert!(!matches("core::build", true, &test));
+    }
+
+    #[test]
+    fn matches_a_name_with_the_separator() {
+        let test = test("multitest.toml", "a::b");
+        assert!(matches("a::b", false, &test));
+        assert!(matches("a::b", true, &test));
+        assert!(!matches("a::c", false, &test));
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// How many commands in their own process group, like the services, can be killed when a
+/// second Ctrl-C exits immediately.
+const MAX_PROCESS_GROUPS: usize = 64;
+// Only used to initialize the slots, each one being its own atomic.
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_PROCESS_GROUP: AtomicUsize = AtomicUsize::new(0);
+/// The process groups of these running commands, or 0 for a free slot. They are in atomics rather
+/// than behind a mutex, since the signal handler reads them.
+static PROCESS_GROUPS: [AtomicUsize; MAX_PROCESS_GROUPS] = [NO_PROCESS_GROUP; MAX_PROCESS_GROUPS];
+
+/// Whether Ctrl-C was pressed since `catch_interrupts` was called.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Remember the process group of a command running in its own process group, like a service,
+/// so that it is killed if a second Ctrl-C exits immediately, since it doesn't receive SIGINT.
+/// Beyond `MAX_PROCESS_GROUPS` commands, it is not.
+pub fn register_process_group(id: u32) {
+    for slot in PROCESS_GROUPS.iter() {
+        if slot
+            .compare_exchange(0, id as usize, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return;
+        }
+    }
+}
+
+/// Forget the process group of a command once it is finished.
+pub fn unregister_process_group(id: u32) {
+    for slot in PROCESS_GROUPS.iter() {
+        if slot
+            .compare_exchange(id as usize, 0, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return;
+        }
+    }
+}
+
+/// Catch SIGINT instead of exiting, so that the teardown commands can still be run. The tests
+/// in the same process group as multitest receive the signal too. A second Ctrl-C exits
+/// immediately, after killing the process groups of the running services and of the other
+/// commands in their own process group.
+#[cfg(unix)]
+pub fn catch_interrupts() {
+    extern "C" fn handler(_signal: libc::c_int) {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            for slot in PROCESS_GROUPS.iter() {
+                let id = slot.load(Ordering::SeqCst);
+                if id != 0 {
+                    unsafe {
+                        libc::kill(-(id as libc::pid_t), libc::SIGKILL);
+                    }
+                }
+            }
+
+            unsafe {
+                libc::_exit(130);
+            }
+        }
+    }
+
+    unsafe {
+        libc::signal(libc::SIGINT, handler as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn catch_interrupts() {}
@@ -0,0 +1,389 @@
+use config::{RunConfigResult, TestResult};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::Duration;
+use test::Outcome;
+
+/// Escape a string so it can be used in XML text and attribute values. The characters that are
+/// not allowed in XML documents are removed.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' || c == '\u{FFFE}' || c == '\u{FFFF}' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{}.{:03}", duration.as_secs(), duration.subsec_millis())
+}
+
+/// A testsuite: the tests of one configuration file, or its setup and teardown commands and its
+/// services that failed.
+struct Suite<'a> {
+    config_file: &'a Path,
+    hooks: bool,
+    results: Vec<&'a TestResult>,
+    ignored: Vec<&'a str>,
+    not_run: Vec<&'a str>,
+}
+
+impl<'a> Suite<'a> {
+    fn name(&self) -> String {
+        let config_file = self.config_file.to_string_lossy();
+        if self.hooks {
+            format!("{} (hooks)", config_file)
+        } else {
+            config_file.into_owned()
+        }
+    }
+
+    fn tests(&self) -> usize {
+        self.results.len() + self.ignored.len() + self.not_run.len()
+    }
+
+    fn count(&self, outcome: Outcome) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.outcome == outcome)
+            .count()
+    }
+
+    fn failures(&self) -> usize {
+        self.count(Outcome::Failure) + self.count(Outcome::TimedOut)
+    }
+
+    /// The tests that were ignored by the filter, that were not run because of the failures, of a
+    /// setup command or of Ctrl-C, or that skipped themselves.
+    fn skipped(&self) -> usize {
+        self.ignored.len() + self.not_run.len() + self.count(Outcome::Skipped)
+    }
+
+    fn duration(&self) -> Duration {
+        self.results
+            .iter()
+            .fold(Duration::from_secs(0), |total, result| total + result.duration)
+    }
+}
+
+fn find_or_add_suite<'a, 'b>(
+    suites: &'b mut Vec<Suite<'a>>,
+    config_file: &'a Path,
+    hooks: bool,
+) -> &'b mut Suite<'a> {
+    match suites
+        .iter()
+        .position(|suite| suite.config_file == config_file && suite.hooks == hooks)
+    {
+        Some(index) => &mut suites[index],
+        None => {
+            suites.push(Suite {
+                config_file,
+                hooks,
+                results: vec![],
+                ignored: vec![],
+                not_run: vec![],
+            });
+            suites.last_mut().unwrap()
+        }
+    }
+}
+
+/// Group the tests by configuration file, in the order the files were first seen. The setup and
+/// teardown commands and the services that failed are in their own suites, after the tests, so
+/// that they are not counted as tests of their file.
+fn suites<'a>(result: &'a RunConfigResult) -> Vec<Suite<'a>> {
+    let mut suites = vec![];
+
+    for test_result in &result.results {
+        find_or_add_suite(&mut suites, &test_result.config_file, false)
+            .results
+            .push(test_result);
+    }
+
+    for (config_file, name) in &result.ignored {
+        find_or_add_suite(&mut suites, config_file, false)
+            .ignored
+            .push(name);
+    }
+
+    for (config_file, name) in &result.not_run {
+        find_or_add_suite(&mut suites, config_file, false)
+            .not_run
+            .push(name);
+    }
+
+    for hook_result in &result.hook_failures {
+        find_or_add_suite(&mut suites, &hook_result.config_file, true)
+            .results
+            .push(hook_result);
+    }
+
+    suites
+}
+
+/// Write a test that was not run.
+fn write_skipped_testcase<W: Write>(
+    writer: &mut W,
+    classname: &str,
+    name: &str,
+    message: &str,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "    <testcase name=\"{}\" classname=\"{}\" time=\"0.000\">",
+        escape(name),
+        classname
+    )?;
+    writeln!(writer, "      <skipped message=\"{}\"/>", message)?;
+    writeln!(writer, "    </testcase>")
+}
+
+fn write_testcase<W: Write>(
+    writer: &mut W,
+    classname: &str,
+    result: &TestResult,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\">",
+        escape(&result.name),
+        classname,
+        seconds(result.duration)
+    )?;
+
+    let mut properties = vec![];
+    if let Some(exit_code) = result.exit_code {
+        properties.push(("exit_code", exit_code.to_string()));
+    }
+    if result.attempts > 1 {
+        properties.push(("attempts", result.attempts.to_string()));
+    }
+
+    if !properties.is_empty() {
+        writeln!(writer, "      <properties>")?;
+        for (name, value) in properties {
+            writeln!(
+                writer,
+                "        <property name=\"{}\" value=\"{}\"/>",
+                name, value
+            )?;
+        }
+        writeln!(writer, "      </properties>")?;
+    }
+
+    let message = escape(result.failure_message.as_ref().map_or("", |m| &**m));
+    match result.outcome {
+        Outcome::Success | Outcome::Flaky => {}
+        Outcome::Failure => {
+            writeln!(writer, "      <failure message=\"{}\"/>", message)?;
+        }
+        Outcome::TimedOut => {
+            writeln!(
+                writer,
+                "      <failure message=\"{}\" type=\"timeout\"/>",
+                message
+            )?;
+        }
+        Outcome::Skipped => {
+            writeln!(writer, "      <skipped message=\"{}\"/>", message)?;
+        }
+        Outcome::Error => {
+            writeln!(writer, "      <error message=\"{}\"/>", message)?;
+        }
+    }
+
+    if !result.output.is_empty() {
+        writeln!(
+            writer,
+            "      <system-out>{}</system-out>",
+            escape(&String::from_utf8_lossy(&result.output))
+        )?;
+    }
+
+    // The logs of the services used by a failed test are attached as its stderr.
+    if !result.service_logs.is_empty() {
+        let mut logs = String::new();
+        for (name, log) in &result.service_logs {
+            logs.push_str(&format!("Log of service {}:\n", name));
+            logs.push_str(&String::from_utf8_lossy(log));
+        }
+        writeln!(writer, "      <system-err>{}</system-err>", escape(&logs))?;
+    }
+
+    writeln!(writer, "    </testcase>")
+}
+
+fn write_suites<W: Write>(writer: &mut W, result: &RunConfigResult) -> io::Result<()> {
+    let suites = suites(result);
+    let tests = suites.iter().map(Suite::tests).sum::<usize>();
+    let failures = suites.iter().map(Suite::failures).sum::<usize>();
+    let errors = suites
+        .iter()
+        .map(|suite| suite.count(Outcome::Error))
+        .sum::<usize>();
+    let skipped = suites.iter().map(Suite::skipped).sum::<usize>();
+    let duration = suites
+        .iter()
+        .fold(Duration::from_secs(0), |total, suite| total + suite.duration());
+
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        writer,
+        "<testsuites name=\"multitest\" tests=\"{}\" failures=\"{}\" errors=\"{}\" \
+         skipped=\"{}\" time=\"{}\">",
+        tests,
+        failures,
+        errors,
+        skipped,
+        seconds(duration)
+    )?;
+
+    for suite in &suites {
+        let name = escape(&suite.name());
+
+        writeln!(
+            writer,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" \
+             skipped=\"{}\" time=\"{}\">",
+            name,
+            suite.tests(),
+            suite.failures(),
+            suite.count(Outcome::Error),
+            suite.skipped(),
+            seconds(suite.duration())
+        )?;
+
+        for result in &suite.results {
+            write_testcase(writer, &name, result)?;
+        }
+
+        for ignored in &suite.ignored {
+            write_skipped_testcase(writer, &name, ignored, "ignored by the filter")?;
+        }
+
+        for not_run in &suite.not_run {
+            write_skipped_testcase(writer, &name, not_run, "not run")?;
+        }
+
+        writeln!(writer, "  </testsuite>")?;
+    }
+
+    writeln!(writer, "</testsuites>")
+}
+
+/// Write a JUnit XML report, with one testsuite per configuration file.
+pub fn write_report(path: &Path, result: &RunConfigResult) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_suites(&mut writer, result)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test_result(config_file: &str, name: &str, outcome: Outcome) -> TestResult {
+        TestResult {
+            name: name.to_string(),
+            config_file: PathBuf::from(config_file),
+            outcome,
+            duration: Duration::from_millis(1500),
+            exit_code: None,
+            attempts: 1,
+            failure_message: None,
+            output: vec![],
+            service_logs: vec![],
+        }
+    }
+
+    fn not_run(config_file: &str, name: &str) -> (PathBuf, String) {
+        (PathBuf::from(config_file), name.to_string())
+    }
+
+    fn report(result: &RunConfigResult) -> Vec<String> {
+        let mut report = vec![];
+        write_suites(&mut report, result).unwrap();
+        String::from_utf8(report)
+            .unwrap()
+            .lines()
+            .filter(|line| line.contains("<testsuite"))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn counts_every_test_of_the_suites() {
+        let result = RunConfigResult {
+            ignored: vec![not_run("a.toml", "ignored")],
+            results: vec![
+                test_result("a.toml", "success", Outcome::Success),
+                test_result("b.toml", "failure", Outcome::Failure),
+            ],
+            not_run: vec![not_run("b.toml", "not run"), not_run("b.toml", "other")],
+            hook_failures: vec![],
+            interrupted: true,
+            rerun_failed: false,
+        };
+
+        assert_eq!(
+            report(&result),
+            vec![
+                "<testsuites name=\"multitest\" tests=\"5\" failures=\"1\" errors=\"0\" \
+                 skipped=\"3\" time=\"3.000\">",
+                "  <testsuite name=\"a.toml\" tests=\"2\" failures=\"0\" errors=\"0\" \
+                 skipped=\"1\" time=\"1.500\">",
+                "  <testsuite name=\"b.toml\" tests=\"3\" failures=\"1\" errors=\"0\" \
+                 skipped=\"2\" time=\"1.500\">",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_failed_hooks_in_their_own_suite() {
+        let mut setup = test_result("a.toml", "setup", Outcome::Failure);
+        setup.duration = Duration::from_secs(1);
+        let result = RunConfigResult {
+            ignored: vec![],
+            results: vec![test_result("a.toml", "success", Outcome::Success)],
+            not_run: vec![not_run("b.toml", "not run")],
+            hook_failures: vec![
+                setup,
+                test_result("b.toml", "service", Outcome::Error),
+                test_result("a.toml", "teardown", Outcome::Failure),
+            ],
+            interrupted: false,
+            rerun_failed: false,
+        };
+
+        assert_eq!(
+            report(&result),
+            vec![
+                "<testsuites name=\"multitest\" tests=\"5\" failures=\"2\" errors=\"1\" \
+                 skipped=\"1\" time=\"5.500\">",
+                "  <testsuite name=\"a.toml\" tests=\"1\" failures=\"0\" errors=\"0\" \
+                 skipped=\"0\" time=\"1.500\">",
+                "  <testsuite name=\"b.toml\" tests=\"1\" failures=\"0\" errors=\"0\" \
+                 skipped=\"1\" time=\"0.000\">",
+                "  <testsuite name=\"a.toml (hooks)\" tests=\"2\" failures=\"2\" errors=\"0\" \
+                 skipped=\"0\" time=\"2.500\">",
+                "  <testsuite name=\"b.toml (hooks)\" tests=\"1\" failures=\"0\" errors=\"1\" \
+                 skipped=\"0\" time=\"1.500\">",
+            ]
+        );
+    }
+}
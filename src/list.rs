@@ -0,0 +1,59 @@
+use config::{config_dir, LoadedConfig};
+use std::path::Path;
+use test::Test;
+
+/// Print the name of the tests that match the filter, followed by the configuration file they
+/// come from if it is not the root one.
+pub fn list(root_config_file: &Path, loaded: &LoadedConfig) {
+    for test in &loaded.tests {
+        match test.config_file {
+            Some(ref config_file) if config_file != root_config_file => {
+                println!("{} ({})", test.name, config_file.display());
+            }
+            _ => println!("{}", test.name),
+        }
+    }
+}
+
+/// Print a test or hook with its kind, like `Test` or `Setup`.
+fn print_command(kind: &str, test: &Test<String, String, String>) {
+    match test.config_file {
+        Some(ref config_file) => println!(
+            "{} {} in {}: {}",
+            kind,
+            test.name,
+            config_dir(config_file).display(),
+            test
+        ),
+        None => println!("{} {}: {}", kind, test.name, test),
+    }
+}
+
+/// Print the command line of every test, with its environment variables and working directory,
+/// without running it. The working directory is shown relative to the directory of the
+/// configuration file, in which the command line is shown to be run. The setup commands and the
+/// services are printed before the tests, and the teardown commands after them.
+pub fn dry_run(loaded: &LoadedConfig) {
+    for hooks in &loaded.hooks {
+        for setup in &hooks.setup {
+            print_command("Setup", setup);
+        }
+        for service in &hooks.services {
+            print_command("Service", &service.test);
+        }
+    }
+
+    for test in &loaded.tests {
+        print_command("Test", test);
+    }
+
+    for hooks in loaded.hooks.iter().rev() {
+        for teardown in &hooks.teardown {
+            print_command("Teardown", teardown);
+        }
+    }
+
+    for test in &loaded.ignored {
+        println!("Test {} ignored by the filter: {}", test.name, test);
+    }
+}
@@ -1,9 +1,15 @@
 extern crate atty;
 #[macro_use]
 extern crate clap;
+extern crate diff;
 extern crate glob;
+extern crate humantime;
+extern crate libc;
 extern crate liquid;
+extern crate num_cpus;
 extern crate regex;
+#[macro_use]
+extern crate serde_json;
 extern crate shell_escape;
 extern crate termcolor;
 extern crate toml;
@@ -12,19 +18,130 @@ extern crate toml;
 mod eprint;
 mod cli;
 mod config;
+mod covering;
+mod error;
+mod events;
+mod expect;
+mod filter;
+mod interrupt;
+mod junit;
+mod list;
+mod runner;
+mod service;
+mod state;
+mod tags;
+mod template;
 mod test;
 
-use regex::Regex;
+use config::RunOptions;
+use error::ConfigError;
+use events::Format;
+use filter::Filter;
+use humantime::parse_duration;
+use runner::ShowOutput;
+use state::State;
+use std::env;
+use std::path::PathBuf;
 use std::process::exit;
+use std::time::Instant;
+use tags::TagExpr;
 use termcolor::ColorChoice;
 
+fn report_config_errors(errors: &[ConfigError], format: Format, start: Instant) {
+    match format {
+        Format::Human => {
+            for error in errors {
+                eprintln_red!("Error: {}", error);
+            }
+            if errors.len() > 1 {
+                eprintln_red!("{} errors in the configuration", errors.len());
+            }
+        }
+        Format::Json => {
+            for error in errors {
+                events::config_error(error);
+            }
+            events::run_finished(&Default::default(), false, start.elapsed());
+        }
+    }
+}
+
+/// Parse the filters given on the command line, or exit with an error if one is invalid.
+fn parse_filters(filters: Option<clap::Values>, exact: bool) -> Vec<Filter> {
+    let filters = match filters {
+        Some(filters) => filters,
+        None => return vec![],
+    };
+
+    filters
+        .map(|filter| match Filter::new(filter, exact) {
+            Ok(filter) => filter,
+            Err(error) => clap::Error::value_validation_auto(format!(
+                "the filter {:?} is invalid: {}",
+                filter, error
+            )).exit(),
+        })
+        .collect()
+}
+
 fn main() {
-    let matches = cli::build_cli().get_matches();
+    let start = Instant::now();
+    let app_matches = cli::build_cli().get_matches();
+
+    // The arguments are global, so they are also available to the subcommand.
+    let list = app_matches.subcommand_matches("list");
+    let matches = list.unwrap_or(&app_matches);
+    let dry_run = matches.is_present("dry_run");
 
     let config_file = matches.value_of_os("config_file");
-    let filter = matches
-        .value_of("filter")
-        .map(|filter| Regex::new(filter).unwrap());
+    let exact = matches.is_present("exact");
+    let filters = parse_filters(matches.values_of("filter"), exact);
+    let excludes = parse_filters(matches.values_of("exclude"), exact);
+    let tag = matches
+        .value_of("tag")
+        .map(|tag| TagExpr::parse(tag).unwrap());
+    let exclude_tag = matches
+        .value_of("exclude_tag")
+        .map(|exclude_tag| TagExpr::parse(exclude_tag).unwrap());
+    let rerun_failed = matches.is_present("rerun_failed");
+    let failed_first = matches.is_present("failed_first");
+    let max_failures = if matches.is_present("fail_fast") {
+        Some(1)
+    } else {
+        matches
+            .value_of("max_failures")
+            .map(|max_failures| max_failures.parse().unwrap())
+    };
+    let retries = matches
+        .value_of("retries")
+        .map(|retries| retries.parse().unwrap());
+    // Like most tools, a continuous integration service is detected with the CI variable.
+    let ci = match env::var_os("CI") {
+        Some(ci) => !ci.is_empty(),
+        None => false,
+    };
+    let jobs = match matches.value_of("jobs") {
+        Some(jobs) => jobs.parse().unwrap(),
+        None => num_cpus::get(),
+    };
+    let timeout = matches
+        .value_of("timeout")
+        .map(|timeout| parse_duration(timeout).unwrap());
+    let timeout_grace = matches
+        .value_of("timeout_grace")
+        .map(|timeout_grace| parse_duration(timeout_grace).unwrap());
+    let junit = matches.value_of_os("junit").map(PathBuf::from);
+    let show_output = match matches.value_of("show_output").unwrap() {
+        "always" => ShowOutput::Always,
+        "failures" => ShowOutput::Failures,
+        "never" => ShowOutput::Never,
+        _ => unreachable!(),
+    };
+    let format = match matches.value_of("format").unwrap() {
+        "human" => Format::Human,
+        "json" => Format::Json,
+        _ => unreachable!(),
+    };
     let color_choice = match matches.value_of("color").unwrap() {
         "always" => ColorChoice::Always,
         "auto" => if atty::is(atty::Stream::Stderr) {
@@ -40,12 +157,110 @@ fn main() {
         eprint::set_color_choice(color_choice);
     }
 
-    let success = match config::run_config_root(config_file, &filter) {
+    let config_filename = match config::root_config_file(config_file) {
+        Ok(config_filename) => config_filename,
+        Err(errors) => {
+            report_config_errors(&errors, format, start);
+            exit(1);
+        }
+    };
+
+    // The state of the last run is kept next to the root configuration file.
+    let state_dir = config::config_dir(&config_filename).to_path_buf();
+    let last_run = match State::load(&state_dir) {
+        Ok(last_run) => last_run,
+        Err(error) => {
+            eprintln_red!(
+                "Cannot read {}: {}",
+                State::path(&state_dir).display(),
+                error
+            );
+            Default::default()
+        }
+    };
+
+    let options = RunOptions {
+        filters,
+        excludes,
+        tag,
+        exclude_tag,
+        rerun_failed,
+        failed_first,
+        last_run,
+        max_failures,
+        retries,
+        ci,
+        jobs,
+        timeout,
+        timeout_grace,
+        show_output,
+        capture_output: junit.is_some(),
+        format,
+    };
+
+    if list.is_some() || dry_run {
+        let success = match config::load_config(&config_filename, &options, false) {
+            Ok(loaded) => {
+                if format == Format::Human {
+                    if list.is_some() {
+                        list::list(&config_filename, &loaded);
+                    } else {
+                        list::dry_run(&loaded);
+                    }
+                }
+                true
+            }
+            Err(errors) => {
+                report_config_errors(&errors, format, start);
+                false
+            }
+        };
+
+        exit(if success { 0 } else { 1 });
+    }
+
+    // Ctrl-C stops the run, but the teardown commands are still run.
+    interrupt::catch_interrupts();
+
+    let success = match config::run_config_root(&config_filename, &options) {
         Ok(result) => {
-            result.summary();
-            result.is_success()
+            let mut state = options.last_run;
+            state.update(&result);
+            if let Err(error) = state.save(&state_dir) {
+                eprintln_red!(
+                    "Cannot write {}: {}",
+                    State::path(&state_dir).display(),
+                    error
+                );
+            }
+
+            if format == Format::Human {
+                result.summary(options.show_output);
+            }
+
+            let junit_written = match junit {
+                Some(ref junit) => match junit::write_report(junit, &result) {
+                    Ok(()) => true,
+                    Err(error) => {
+                        eprintln_red!("Cannot write {}: {}", junit.display(), error);
+                        false
+                    }
+                },
+                None => true,
+            };
+
+            let success = result.is_success() && junit_written;
+
+            if format == Format::Json {
+                events::run_finished(&result, success, start.elapsed());
+            }
+
+            success
+        }
+        Err(errors) => {
+            report_config_errors(&errors, format, start);
+            false
         }
-        Err(()) => false,
     };
 
     exit(if success { 0 } else { 1 });
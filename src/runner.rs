@@ -0,0 +1,327 @@
+use config::{RunOptions, TestResult};
+use events::{self, Format};
+use interrupt::interrupted;
+use service::{RunningService, Service};
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+use test::{write_output, Outcome, OutputMode, Test, TestRun};
+
+/// When to show the output of the tests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShowOutput {
+    Always,
+    /// Only show the output of the tests that didn't succeed, in the summary.
+    Failures,
+    Never,
+}
+
+/// The commands run once before and after the tests of a configuration file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookKind {
+    Setup,
+    Teardown,
+}
+
+impl HookKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            HookKind::Setup => "setup",
+            HookKind::Teardown => "teardown",
+        }
+    }
+}
+
+enum Message {
+    Started(usize),
+    Finished(usize, TestRun),
+}
+
+/// How the output of the tests is handled.
+fn output_mode(options: &RunOptions) -> OutputMode {
+    let capture_output = options.capture_output || options.format == Format::Json;
+
+    match options.show_output {
+        ShowOutput::Always if options.jobs <= 1 && !capture_output => OutputMode::Inherit,
+        ShowOutput::Never if !capture_output => OutputMode::Discard,
+        _ => OutputMode::Capture,
+    }
+}
+
+/// Whether the output of a test or hook is shown when it is finished.
+fn include_output(show_output: ShowOutput, test_run: &TestRun) -> bool {
+    match show_output {
+        ShowOutput::Always => true,
+        ShowOutput::Failures => test_run.outcome().is_failure(),
+        ShowOutput::Never => false,
+    }
+}
+
+/// Run a setup or teardown command. A teardown command is run even if Ctrl-C was pressed, and
+/// is not interrupted by it.
+pub fn run_hook(
+    kind: HookKind,
+    hook: &Test<String, String, String>,
+    options: &RunOptions,
+) -> TestRun {
+    // The name of a hook is only shown if it was given.
+    let label = if hook.name == kind.name() {
+        hook.name.clone()
+    } else {
+        format!("{} {}", kind.name(), hook.name)
+    };
+
+    match options.format {
+        Format::Human => eprintln_bold!("Running {} ({})", label, hook),
+        Format::Json => events::hook_started(kind, hook),
+    }
+
+    let output_mode = output_mode(options);
+    let test_run = match kind {
+        HookKind::Setup => hook.run_with_retries(output_mode),
+        HookKind::Teardown => hook.run_command(output_mode, false),
+    };
+
+    match options.format {
+        Format::Human => {
+            if options.show_output == ShowOutput::Always && !test_run.output.is_empty() {
+                eprintln_bold!("Output of {}:", label);
+                write_output(&test_run.output);
+            }
+            if test_run.outcome().is_failure() {
+                eprintln_red!(
+                    "The {} failed: {}",
+                    label,
+                    test_run.failure_message().unwrap_or_default()
+                );
+            }
+        }
+        Format::Json => {
+            let include_output = include_output(options.show_output, &test_run);
+            events::hook_finished(kind, hook, &test_run, include_output);
+        }
+    }
+
+    test_run
+}
+
+/// The result of a service that failed, with its log as output.
+fn service_failure(
+    service: &Service,
+    message: String,
+    log: Vec<u8>,
+    duration: Duration,
+) -> TestResult {
+    TestResult {
+        name: service.test.name.clone(),
+        config_file: service.test.config_file.clone().unwrap_or_default(),
+        outcome: Outcome::Error,
+        duration,
+        exit_code: None,
+        attempts: 1,
+        failure_message: Some(message),
+        output: log,
+        service_logs: vec![],
+    }
+}
+
+/// Start a service and wait until it is ready. If it fails, its result is returned instead.
+pub fn start_service(
+    service: &Service,
+    options: &RunOptions,
+) -> Result<RunningService, Box<TestResult>> {
+    let name = &service.test.name;
+
+    match options.format {
+        Format::Human => eprintln_bold!("Starting service {} ({})", name, service.test),
+        Format::Json => events::service_starting(&service.test),
+    }
+
+    let start = Instant::now();
+    let started = service.start();
+
+    match options.format {
+        Format::Human => match started {
+            Ok(_) => eprintln_green!("Service {} is ready", name),
+            Err(ref failure) => {
+                if options.show_output == ShowOutput::Always && !failure.log.is_empty() {
+                    eprintln_bold!("Log of service {}:", name);
+                    write_output(&failure.log);
+                }
+                eprintln_red!("The service {} failed: {}", name, failure.message);
+            }
+        },
+        Format::Json => {
+            let message = started.as_ref().err().map(|failure| &*failure.message);
+            events::service_started(&service.test, message, start.elapsed());
+        }
+    }
+
+    started.map_err(|failure| {
+        Box::new(service_failure(service, failure.message, failure.log, start.elapsed()))
+    })
+}
+
+/// Stop a service, and return its log, with its result if it exited before being stopped. The
+/// log is shown if `show_log` is true and the output of the tests is shown as soon as they
+/// finish.
+pub fn stop_service(
+    service: &Service,
+    running: RunningService,
+    show_log: bool,
+    options: &RunOptions,
+) -> (Option<TestResult>, Vec<u8>) {
+    let name = &service.test.name;
+    if options.format == Format::Human {
+        eprintln_bold!("Stopping service {}", name);
+    }
+
+    let (message, log) = running.stop(service.test.timeout_grace);
+
+    match options.format {
+        Format::Human => {
+            if show_log && options.show_output == ShowOutput::Always && !log.is_empty() {
+                eprintln_bold!("Log of service {}:", name);
+                write_output(&log);
+            }
+            if let Some(ref message) = message {
+                eprintln_red!("The service {} failed: {}", name, message);
+            }
+        }
+        Format::Json => {
+            let include_log = match options.show_output {
+                ShowOutput::Always | ShowOutput::Failures => show_log || message.is_some(),
+                ShowOutput::Never => false,
+            };
+            events::service_stopped(
+                &service.test,
+                &message,
+                if include_log { Some(&*log) } else { None },
+            );
+        }
+    }
+
+    let failure = message.map(|message| {
+        service_failure(service, message, log.clone(), Duration::from_secs(0))
+    });
+    (failure, log)
+}
+
+/// Run the tests using up to `jobs` threads. Return each test with its result, in the same order
+/// as `tests`. Once `max_failures` tests failed, or once Ctrl-C was pressed, no other test is
+/// started and the tests that were not run have no result.
+///
+/// When more than one job is used, the output of the tests is captured and only printed once a
+/// test is finished, so the output of concurrent tests doesn't interleave. The output is also
+/// always captured with the JSON format, so that it doesn't mix with the events.
+pub fn run_tests(
+    tests: Vec<Test<String, String, String>>,
+    options: &RunOptions,
+) -> Vec<(Test<String, String, String>, Option<TestRun>)> {
+    let jobs = options.jobs;
+    let max_failures = options.max_failures;
+    let show_output = options.show_output;
+    let output_mode = output_mode(options);
+
+    let started = |test: &Test<String, String, String>| match options.format {
+        Format::Human => eprintln_bold!("Running test {} ({})", test.name, test),
+        Format::Json => events::test_started(test),
+    };
+
+    let finished = |test: &Test<String, String, String>, test_run: &TestRun| match options.format {
+        Format::Human => {
+            test_run.report(&test.name, show_output == ShowOutput::Always);
+        }
+        Format::Json => {
+            events::test_finished(test, test_run, include_output(show_output, test_run));
+        }
+    };
+
+    let should_stop = move |failures: usize| match max_failures {
+        Some(max_failures) => failures >= max_failures || interrupted(),
+        None => interrupted(),
+    };
+
+    if jobs <= 1 {
+        let mut failures = 0;
+
+        return tests
+            .into_iter()
+            .map(|test| {
+                if should_stop(failures) {
+                    return (test, None);
+                }
+
+                started(&test);
+                let test_run = test.run_with_retries(output_mode);
+                finished(&test, &test_run);
+                if test_run.outcome().is_failure() {
+                    failures += 1;
+                }
+                (test, Some(test_run))
+            })
+            .collect();
+    }
+
+    let tests = Arc::new(tests);
+    let next_test = Arc::new(AtomicUsize::new(0));
+    let failures = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = channel();
+
+    let workers: Vec<_> = (0..cmp::min(jobs, tests.len()))
+        .map(|_| {
+            let tests = tests.clone();
+            let next_test = next_test.clone();
+            let failures = failures.clone();
+            let sender = sender.clone();
+
+            thread::spawn(move || loop {
+                if should_stop(failures.load(Ordering::SeqCst)) {
+                    break;
+                }
+
+                let index = next_test.fetch_add(1, Ordering::SeqCst);
+                if index >= tests.len() {
+                    break;
+                }
+
+                sender.send(Message::Started(index)).unwrap();
+                let test_run = tests[index].run_with_retries(output_mode);
+                if test_run.outcome().is_failure() {
+                    failures.fetch_add(1, Ordering::SeqCst);
+                }
+                sender.send(Message::Finished(index, test_run)).unwrap();
+            })
+        })
+        .collect();
+
+    // The receiver stops once every worker has dropped its sender.
+    drop(sender);
+
+    let mut results: Vec<_> = (0..tests.len()).map(|_| None).collect();
+
+    for message in receiver {
+        match message {
+            Message::Started(index) => started(&tests[index]),
+            Message::Finished(index, test_run) => {
+                finished(&tests[index], &test_run);
+                results[index] = Some(test_run);
+            }
+        }
+    }
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    // Every worker has been joined, so there is no other reference to the tests left.
+    let tests = match Arc::try_unwrap(tests) {
+        Ok(tests) => tests,
+        Err(_) => unreachable!(),
+    };
+
+    tests.into_iter().zip(results).collect()
+}
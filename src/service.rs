@@ -0,0 +1,174 @@
+use humantime::format_duration;
+use interrupt::{interrupted, register_process_group, unregister_process_group};
+use regex::Regex;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpStream};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use test::{terminate, Test};
+
+/// The default time given to a service to be ready.
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The time between two checks of the readiness of a service.
+const READY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How to know that a service is ready to be used by the tests.
+#[derive(Clone, Debug)]
+pub enum Readiness {
+    /// A TCP port of localhost accepts connections.
+    Port(u16),
+    /// The output of the service matches a regex.
+    Log(Regex),
+    /// A command succeeds. It is run from the working directory of the service.
+    Command(Vec<String>),
+}
+
+/// A long-running command started before the tests of a configuration file and stopped after
+/// them. Without a readiness check, it is ready as soon as it is started.
+pub struct Service {
+    pub test: Test<String, String, String>,
+    pub ready: Option<Readiness>,
+    pub ready_timeout: Duration,
+}
+
+/// A service that was started. Its output is kept as its log.
+pub struct RunningService {
+    child: Child,
+    log: Arc<Mutex<Vec<u8>>>,
+}
+
+/// Why a service couldn't be started, with its log.
+pub struct ServiceFailure {
+    pub message: String,
+    pub log: Vec<u8>,
+}
+
+fn describe_status(status: ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exit code {}", code),
+        None => "no exit code".to_string(),
+    }
+}
+
+impl Readiness {
+    fn is_ready(&self, service: &Test<String, String, String>, log: &Mutex<Vec<u8>>) -> bool {
+        match *self {
+            Readiness::Port(port) => {
+                let address = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), port));
+                TcpStream::connect_timeout(&address, READY_INTERVAL).is_ok()
+            }
+            Readiness::Log(ref regex) => {
+                regex.is_match(&String::from_utf8_lossy(&log.lock().unwrap()))
+            }
+            Readiness::Command(ref command) => {
+                let mut command_builder = Command::new(&command[0]);
+                command_builder
+                    .args(&command[1..])
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null());
+                if let Some(ref cwd) = service.cwd {
+                    command_builder.current_dir(cwd);
+                }
+
+                match command_builder.status() {
+                    Ok(status) => status.success(),
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            Readiness::Port(port) => format!("port {} is not open", port),
+            Readiness::Log(ref regex) => format!("its output doesn't match \"{}\"", regex),
+            Readiness::Command(ref command) => format!("\"{}\" doesn't succeed", command.join(" ")),
+        }
+    }
+}
+
+impl Service {
+    /// Start the service and wait until it is ready. It is stopped if it isn't ready in time or
+    /// if Ctrl-C is pressed.
+    pub fn start(&self) -> Result<RunningService, ServiceFailure> {
+        let log = Arc::new(Mutex::new(vec![]));
+        let child = match self.test.spawn_service(&log) {
+            Ok(child) => {
+                register_process_group(child.id());
+                child
+            }
+            Err(error) => {
+                return Err(ServiceFailure {
+                    message: error.to_string(),
+                    log: vec![],
+                })
+            }
+        };
+
+        let mut running = RunningService { child, log };
+        let ready = match self.ready {
+            Some(ref ready) => ready,
+            None => return Ok(running),
+        };
+        let start = Instant::now();
+
+        loop {
+            if let Ok(Some(status)) = running.child.try_wait() {
+                let message = format!("exited before being ready: {}", describe_status(status));
+                return Err(running.fail(message, self.test.timeout_grace));
+            }
+
+            if ready.is_ready(&self.test, &running.log) {
+                return Ok(running);
+            }
+
+            if start.elapsed() >= self.ready_timeout {
+                let message = format!(
+                    "not ready after {}: {}",
+                    format_duration(self.ready_timeout),
+                    ready.describe()
+                );
+                return Err(running.fail(message, self.test.timeout_grace));
+            }
+
+            if interrupted() {
+                return Err(running.fail("interrupted".to_string(), self.test.timeout_grace));
+            }
+
+            thread::sleep(READY_INTERVAL);
+        }
+    }
+}
+
+impl RunningService {
+    fn fail(self, message: String, grace: Duration) -> ServiceFailure {
+        let (_, log) = self.stop(grace);
+        ServiceFailure { message, log }
+    }
+
+    /// Stop the service: its process group is sent SIGTERM, and then SIGKILL if it is still
+    /// running after the grace period. Return its log, and why it failed if it exited by itself
+    /// before being stopped.
+    pub fn stop(mut self, grace: Duration) -> (Option<String>, Vec<u8>) {
+        let failure = match self.child.try_wait() {
+            Ok(Some(status)) => Some(format!(
+                "exited before the end of the tests: {}",
+                describe_status(status)
+            )),
+            _ => {
+                let _ = terminate(&mut self.child, grace);
+                None
+            }
+        };
+        unregister_process_group(self.child.id());
+
+        // The last output of the service may not be read yet. The reading threads are not
+        // waited for, since the processes the service started outside of its process group could
+        // keep its output open.
+        let log = self.log.lock().unwrap().clone();
+        (failure, log)
+    }
+}
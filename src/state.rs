@@ -0,0 +1,201 @@
+use config::RunConfigResult;
+use events::outcome_name;
+use serde_json::{self, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use test::{Outcome, Test};
+
+/// The directory, next to the root configuration file, where the state of the runs is kept.
+const STATE_DIR: &str = ".multitest";
+const LAST_RUN_FILE: &str = "last-run.json";
+
+const OUTCOMES: [Outcome; 6] = [
+    Outcome::Success,
+    Outcome::Failure,
+    Outcome::TimedOut,
+    Outcome::Skipped,
+    Outcome::Error,
+    Outcome::Flaky,
+];
+
+/// The outcome of the last run of each test, identified by the canonical path of its
+/// configuration file and its name.
+#[derive(Default)]
+pub struct State {
+    outcomes: BTreeMap<(PathBuf, String), Outcome>,
+    /// The canonical path of the configuration files, computed once for each file.
+    canonical_config_files: RefCell<HashMap<PathBuf, PathBuf>>,
+}
+
+impl State {
+    /// The path of the configuration file as it is stored in the state, so that it doesn't
+    /// depend on the current directory.
+    fn canonical_config_file(&self, config_file: &Path) -> PathBuf {
+        self.canonical_config_files
+            .borrow_mut()
+            .entry(config_file.to_path_buf())
+            .or_insert_with(|| {
+                fs::canonicalize(config_file).unwrap_or_else(|_| config_file.to_path_buf())
+            })
+            .clone()
+    }
+
+    pub fn path(state_dir: &Path) -> PathBuf {
+        state_dir.join(STATE_DIR).join(LAST_RUN_FILE)
+    }
+
+    /// Read the state kept in `state_dir`. The state is empty if there was no previous run.
+    pub fn load(state_dir: &Path) -> io::Result<State> {
+        let file = match File::open(State::path(state_dir)) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                return Ok(Default::default())
+            }
+            Err(error) => return Err(error),
+        };
+        let value: Value = serde_json::from_reader(BufReader::new(file))?;
+
+        let mut state: State = Default::default();
+        let tests = value["tests"].as_array().map_or(&[][..], |tests| &tests[..]);
+        for test in tests {
+            let config_file = test["config_file"].as_str();
+            let name = test["name"].as_str();
+            let outcome = OUTCOMES
+                .iter()
+                .find(|&&outcome| Some(outcome_name(outcome)) == test["outcome"].as_str());
+
+            if let (Some(config_file), Some(name), Some(&outcome)) = (config_file, name, outcome) {
+                state
+                    .outcomes
+                    .insert((PathBuf::from(config_file), name.to_string()), outcome);
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Record the outcomes of a run. The outcomes of the tests that were not run are kept.
+    pub fn update(&mut self, result: &RunConfigResult) {
+        for test_result in &result.results {
+            let key = (
+                self.canonical_config_file(&test_result.config_file),
+                test_result.name.clone(),
+            );
+            self.outcomes.insert(key, test_result.outcome);
+        }
+    }
+
+    pub fn save(&self, state_dir: &Path) -> io::Result<()> {
+        let path = State::path(state_dir);
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        let tests: Vec<_> = self.outcomes
+            .iter()
+            .map(|(key, outcome)| {
+                json!({
+                    "config_file": key.0.to_string_lossy(),
+                    "name": key.1,
+                    "outcome": outcome_name(*outcome),
+                })
+            })
+            .collect();
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &json!({ "tests": tests }))?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+
+    /// Whether the test failed, timed out or had an error the last time it was run.
+    pub fn failed(&self, test: &Test<String, String, String>) -> bool {
+        let config_file = match test.config_file {
+            Some(ref config_file) => self.canonical_config_file(config_file),
+            None => return false,
+        };
+
+        match self.outcomes.get(&(config_file, test.name.clone())) {
+            Some(outcome) => outcome.is_failure(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::TestResult;
+    use std::env;
+    use std::process;
+    use std::time::Duration;
+
+    fn test_result(config_file: &Path, name: &str, outcome: Outcome) -> TestResult {
+        TestResult {
+            name: name.to_string(),
+            config_file: config_file.to_path_buf(),
+            outcome,
+            duration: Duration::from_secs(0),
+            exit_code: None,
+            attempts: 1,
+            failure_message: None,
+            output: vec![],
+            service_logs: vec![],
+        }
+    }
+
+    fn run_result(results: Vec<TestResult>) -> RunConfigResult {
+        RunConfigResult {
+            ignored: vec![],
+            results,
+            not_run: vec![],
+            hook_failures: vec![],
+            interrupted: false,
+            rerun_failed: false,
+        }
+    }
+
+    fn test(config_file: &Path, name: &str) -> Test<String, String, String> {
+        let mut test = Test::new(name, vec![], false, vec![]);
+        test.config_file = Some(config_file.to_path_buf());
+        test
+    }
+
+    #[test]
+    fn keeps_the_outcomes_of_the_last_run() {
+        let state_dir = env::temp_dir().join(format!("multitest-state-{}", process::id()));
+        let config_file = state_dir.join("multitest.toml");
+        fs::create_dir_all(&state_dir).unwrap();
+        fs::write(&config_file, "").unwrap();
+
+        let mut state = State::load(&state_dir).unwrap();
+        state.update(&run_result(vec![
+            test_result(&config_file, "failure", Outcome::Failure),
+            test_result(&config_file, "fixed", Outcome::TimedOut),
+            test_result(&config_file, "success", Outcome::Success),
+        ]));
+        state.save(&state_dir).unwrap();
+
+        // The outcomes of the tests that are not run again are kept.
+        let mut state = State::load(&state_dir).unwrap();
+        state.update(&run_result(vec![test_result(
+            &config_file,
+            "fixed",
+            Outcome::Flaky,
+        )]));
+        state.save(&state_dir).unwrap();
+
+        let state = State::load(&state_dir).unwrap();
+        // The configuration file is found with another path too.
+        let other_path = state_dir.join(".").join("multitest.toml");
+        let failed: Vec<_> = ["failure", "fixed", "success", "unknown"]
+            .iter()
+            .map(|name| state.failed(&test(&other_path, name)))
+            .collect();
+        fs::remove_dir_all(&state_dir).unwrap();
+
+        assert_eq!(failed, vec![true, false, false, false]);
+    }
+}
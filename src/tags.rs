@@ -0,0 +1,245 @@
+use std::fmt;
+
+/// A boolean expression on the tags of a test, like `slow and not (nightly or beta)`. `not` has
+/// the highest precedence, then `and`, then `or`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TagExpr {
+    Tag(String),
+    Not(Box<TagExpr>),
+    And(Box<TagExpr>, Box<TagExpr>),
+    Or(Box<TagExpr>, Box<TagExpr>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Tag(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Tag(ref tag) => write!(f, "tag \"{}\"", tag),
+            Token::And => write!(f, "\"and\""),
+            Token::Or => write!(f, "\"or\""),
+            Token::Not => write!(f, "\"not\""),
+            Token::Open => write!(f, "\"(\""),
+            Token::Close => write!(f, "\")\""),
+        }
+    }
+}
+
+fn tokenize(expr: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+
+    fn end_word(word: &mut String, tokens: &mut Vec<Token>) {
+        if word.is_empty() {
+            return;
+        }
+
+        tokens.push(match &**word {
+            "and" => Token::And,
+            "or" => Token::Or,
+            "not" => Token::Not,
+            _ => Token::Tag(word.clone()),
+        });
+        word.clear();
+    }
+
+    for c in expr.chars() {
+        match c {
+            '(' | ')' => {
+                end_word(&mut word, &mut tokens);
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            c if c.is_whitespace() => end_word(&mut word, &mut tokens),
+            c => word.push(c),
+        }
+    }
+    end_word(&mut word, &mut tokens);
+
+    tokens
+}
+
+/// A recursive descent parser of the tokens of an expression.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<TagExpr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = TagExpr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<TagExpr, String> {
+        let mut expr = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = TagExpr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<TagExpr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(TagExpr::Not(Box::new(self.not()?))),
+            Some(Token::Tag(tag)) => Ok(TagExpr::Tag(tag)),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    Some(token) => Err(format!("expected \")\", found {}", token)),
+                    None => Err("expected \")\" at the end".to_string()),
+                }
+            }
+            Some(token) => Err(format!("expected a tag, found {}", token)),
+            None => Err("expected a tag at the end".to_string()),
+        }
+    }
+}
+
+impl TagExpr {
+    pub fn parse(expr: &str) -> Result<TagExpr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(expr),
+            position: 0,
+        };
+
+        let expr = parser.or()?;
+        match parser.next() {
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Ok(expr),
+        }
+    }
+
+    /// Whether a test with these tags matches the expression.
+    pub fn matches<S: AsRef<str>>(&self, tags: &[S]) -> bool {
+        match *self {
+            TagExpr::Tag(ref tag) => tags.iter().any(|other| other.as_ref() == tag),
+            TagExpr::Not(ref expr) => !expr.matches(tags),
+            TagExpr::And(ref left, ref right) => left.matches(tags) && right.matches(tags),
+            TagExpr::Or(ref left, ref right) => left.matches(tags) || right.matches(tags),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(expr: &str) -> TagExpr {
+        TagExpr::parse(expr).unwrap()
+    }
+
+    fn error(expr: &str) -> String {
+        TagExpr::parse(expr).unwrap_err()
+    }
+
+    fn tag(name: &str) -> TagExpr {
+        TagExpr::Tag(name.to_string())
+    }
+
+    fn not(expr: TagExpr) -> TagExpr {
+        TagExpr::Not(Box::new(expr))
+    }
+
+    fn and(left: TagExpr, right: TagExpr) -> TagExpr {
+        TagExpr::And(Box::new(left), Box::new(right))
+    }
+
+    fn or(left: TagExpr, right: TagExpr) -> TagExpr {
+        TagExpr::Or(Box::new(left), Box::new(right))
+    }
+
+    #[test]
+    fn parses_a_tag() {
+        assert_eq!(parse("slow"), tag("slow"));
+        assert_eq!(parse("  slow\t"), tag("slow"));
+    }
+
+    #[test]
+    fn not_has_precedence_over_and() {
+        assert_eq!(parse("not a and b"), and(not(tag("a")), tag("b")));
+        assert_eq!(parse("not not a"), not(not(tag("a"))));
+    }
+
+    #[test]
+    fn and_has_precedence_over_or() {
+        assert_eq!(parse("a or b and c"), or(tag("a"), and(tag("b"), tag("c"))));
+        assert_eq!(parse("a and b or c"), or(and(tag("a"), tag("b")), tag("c")));
+    }
+
+    #[test]
+    fn operators_are_left_associative() {
+        assert_eq!(parse("a or b or c"), or(or(tag("a"), tag("b")), tag("c")));
+        assert_eq!(
+            parse("a and b and c"),
+            and(and(tag("a"), tag("b")), tag("c"))
+        );
+    }
+
+    #[test]
+    fn parses_nested_parentheses() {
+        assert_eq!(
+            parse("slow and not (nightly or beta)"),
+            and(tag("slow"), not(or(tag("nightly"), tag("beta"))))
+        );
+        assert_eq!(
+            parse("((a or b)and(c))"),
+            and(or(tag("a"), tag("b")), tag("c"))
+        );
+    }
+
+    #[test]
+    fn rejects_an_empty_expression() {
+        assert_eq!(error(""), "expected a tag at the end");
+        assert_eq!(error("  "), "expected a tag at the end");
+        assert_eq!(error("()"), "expected a tag, found \")\"");
+    }
+
+    #[test]
+    fn rejects_unbalanced_parentheses() {
+        assert_eq!(error("(a or b"), "expected \")\" at the end");
+        assert_eq!(error("(a b)"), "expected \")\", found tag \"b\"");
+        assert_eq!(error("a or b)"), "unexpected \")\"");
+    }
+
+    #[test]
+    fn rejects_missing_operands() {
+        assert_eq!(error("a and"), "expected a tag at the end");
+        assert_eq!(error("not"), "expected a tag at the end");
+        assert_eq!(error("or a"), "expected a tag, found \"or\"");
+        assert_eq!(error("a and or b"), "expected a tag, found \"or\"");
+        assert_eq!(error("a b"), "unexpected tag \"b\"");
+    }
+
+    #[test]
+    fn matches_tags() {
+        let expr = parse("slow and not (nightly or beta)");
+        assert!(expr.matches(&["slow"]));
+        assert!(expr.matches(&["stable", "slow"]));
+        assert!(!expr.matches(&["slow", "beta"]));
+        assert!(!expr.matches::<&str>(&[]));
+    }
+}
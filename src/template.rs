@@ -0,0 +1,226 @@
+use liquid::{self, Value};
+use liquid::interpreter::{FilterError, FilterResult, FnFilterValue};
+use shell_escape::escape;
+use std::path::Path;
+
+/// The filter added to the outputs of a shell script template.
+const SHELL_ESCAPE_FILTER: &str = "shell_escape";
+/// The filter preventing an output of a shell script template from being escaped.
+const UNESCAPED_FILTER: &str = "unescaped";
+
+/// The parser of the templates of the configuration files: liquid with filters to get the parts
+/// of a path, like `{{ example | stem }}`.
+pub fn parser() -> liquid::Parser {
+    liquid::ParserBuilder::with_liquid()
+        .filter("file_name", file_name as FnFilterValue)
+        .filter("stem", stem as FnFilterValue)
+        .filter("extension", extension as FnFilterValue)
+        .filter("parent", parent as FnFilterValue)
+        .filter(SHELL_ESCAPE_FILTER, shell_escape as FnFilterValue)
+        .filter(UNESCAPED_FILTER, unescaped as FnFilterValue)
+        .build()
+}
+
+/// Make every output of a template, like `{{ name }}`, escaped for a shell, so that the values
+/// of the variables are single words of a shell script. The outputs ending with the `unescaped`
+/// filter are kept as they are.
+pub fn shell_escaped(template: &str) -> String {
+    let mut escaped = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        // Like for liquid, the output ends at the first `}}`, even in a string literal.
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        let output = &rest[start + 2..end];
+        // With whitespace control, like `{{ name -}}`, the filter must be before the dash.
+        let expression = output.trim_end().trim_end_matches('-').trim_end();
+        let last_filter = expression.rsplit('|').next().unwrap_or("").trim();
+
+        escaped.push_str(&rest[..start + 2]);
+        if last_filter == UNESCAPED_FILTER || expression.trim().is_empty() {
+            escaped.push_str(output);
+        } else {
+            escaped.push_str(expression);
+            escaped.push_str(" | ");
+            escaped.push_str(SHELL_ESCAPE_FILTER);
+            escaped.push_str(&output[expression.len()..]);
+        }
+        escaped.push_str("}}");
+        rest = &rest[end + 2..];
+    }
+
+    escaped.push_str(rest);
+    escaped
+}
+
+/// Make an argument of a command, like an element of `args`, a single word of a shell script
+/// once rendered, whatever its literal text and the values of its outputs. `index` gives a
+/// distinct name to the variable capturing each argument of the script.
+pub fn shell_word(template: &str, index: usize) -> String {
+    if !template.contains("{{") && !template.contains("{%") {
+        return escape(template.into()).into_owned();
+    }
+
+    // The outputs are escaped with the whole rendered argument, not on their own.
+    let variable = format!("multitest_word_{}", index);
+    format!(
+        "{{% capture {0} %}}{1}{{% endcapture %}}{{{{ {0} | {2} }}}}",
+        variable, template, SHELL_ESCAPE_FILTER
+    )
+}
+
+fn path_filter<F>(input: &Value, args: &[Value], part: F) -> FilterResult
+where
+    F: FnOnce(&Path) -> Option<String>,
+{
+    no_argument(args)?;
+
+    let input = input.to_str();
+    Ok(Value::scalar(part(Path::new(&*input)).unwrap_or_default()))
+}
+
+fn file_name(input: &Value, args: &[Value]) -> FilterResult {
+    path_filter(input, args, |path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    })
+}
+
+fn stem(input: &Value, args: &[Value]) -> FilterResult {
+    path_filter(input, args, |path| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+    })
+}
+
+fn extension(input: &Value, args: &[Value]) -> FilterResult {
+    path_filter(input, args, |path| {
+        path.extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+    })
+}
+
+fn parent(input: &Value, args: &[Value]) -> FilterResult {
+    path_filter(input, args, |path| {
+        path.parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+    })
+}
+
+fn no_argument(args: &[Value]) -> Result<(), FilterError> {
+    if args.is_empty() {
+        Ok(())
+    } else {
+        Err(FilterError::InvalidArgumentCount(format!(
+            "expected no argument, {} given",
+            args.len()
+        )))
+    }
+}
+
+fn shell_escape(input: &Value, args: &[Value]) -> FilterResult {
+    no_argument(args)?;
+    Ok(Value::scalar(escape(input.to_str()).into_owned()))
+}
+
+fn unescaped(input: &Value, args: &[Value]) -> FilterResult {
+    no_argument(args)?;
+    Ok(input.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> String {
+        render_script(&shell_escaped(template))
+    }
+
+    fn render_script(script: &str) -> String {
+        let mut variables = liquid::Object::new();
+        variables.insert("x".into(), Value::scalar("a b"));
+        variables.insert("path".into(), Value::scalar("dir/it's.txt"));
+
+        parser().parse(script).unwrap().render(&variables).unwrap()
+    }
+
+    #[test]
+    fn escapes_an_output() {
+        assert_eq!(shell_escaped("echo {{ x }}"), "echo {{ x | shell_escape }}");
+        assert_eq!(render("echo {{ x }}"), "echo 'a b'");
+        assert_eq!(render("echo {{x}}"), "echo 'a b'");
+    }
+
+    #[test]
+    fn escapes_an_output_with_whitespace_control() {
+        assert_eq!(shell_escaped("{{- x -}}"), "{{- x | shell_escape -}}");
+        assert_eq!(render("echo {{- x -}} ;"), "echo'a b';");
+        assert_eq!(render("echo {{ x -}} ;"), "echo 'a b';");
+        assert_eq!(render("echo {{-x-}} ;"), "echo'a b';");
+    }
+
+    #[test]
+    fn keeps_an_unescaped_output() {
+        assert_eq!(shell_escaped("{{ x | unescaped }}"), "{{ x | unescaped }}");
+        assert_eq!(render("echo {{ x | unescaped }}"), "echo a b");
+        assert_eq!(render("echo {{ x | unescaped -}} ;"), "echo a b;");
+    }
+
+    #[test]
+    fn escapes_the_result_of_a_filter_chain() {
+        assert_eq!(
+            render("cat {{ path | file_name | upcase }}"),
+            "cat 'IT'\\''S.TXT'"
+        );
+        assert_eq!(
+            render("cat {{ path | unescaped | stem }}"),
+            "cat 'it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn ends_an_output_like_liquid() {
+        let template = "echo {{ \"}}\" | append: x }}";
+        assert!(parser().parse(template).is_err());
+        assert!(parser().parse(&shell_escaped(template)).is_err());
+        assert_eq!(render("echo {{ x | append: '}' }}"), "echo 'a b}'");
+    }
+
+    #[test]
+    fn ignores_the_filters_in_string_literals() {
+        assert_eq!(render("echo {{ x | append: '|' }}"), "echo 'a b|'");
+        assert_eq!(
+            render("echo {{ x | append: \"| unescaped\" }}"),
+            "echo 'a b| unescaped'"
+        );
+    }
+
+    #[test]
+    fn makes_an_argument_a_single_word() {
+        let words = [
+            shell_word("a b; echo injected", 0),
+            shell_word("{{ path | stem }} $HOME", 1),
+            shell_word("{{ x | unescaped }}", 2),
+            shell_word("plain", 3),
+        ];
+        assert_eq!(words[0], "'a b; echo injected'");
+        assert_eq!(
+            render_script(&words.join(" ")),
+            "'a b; echo injected' 'it'\\''s $HOME' 'a b' plain"
+        );
+    }
+
+    #[test]
+    fn keeps_empty_and_unterminated_outputs() {
+        assert_eq!(shell_escaped("echo {{ }} {{"), "echo {{ }} {{");
+        assert_eq!(
+            shell_escaped("echo {{ x }} {{ x"),
+            "echo {{ x | shell_escape }} {{ x"
+        );
+        assert_eq!(render("echo {{ x"), "echo {{ x");
+    }
+}
@@ -1,15 +1,130 @@
+use config::config_dir;
+use expect::{Expectation, Streams};
+use humantime::format_duration;
+use interrupt::{interrupted, register_process_group, unregister_process_group};
 use shell_escape::escape;
 use std::ffi::OsStr;
 use std::fmt;
+use std::fs::File;
 use std::io;
-use std::process::Command;
-use std::process::ExitStatus;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default time given to a test to exit after it was asked to terminate because of a timeout.
+pub const DEFAULT_TIMEOUT_GRACE: Duration = Duration::from_secs(10);
 
 pub struct Test<T1, T2, T3> {
     pub name: String,
     pub command: Vec<T1>,
+    /// The shell running the command, like `sh -c`. The command is then a single script.
+    pub shell: Option<Vec<String>>,
     pub env: Vec<(T2, T3)>,
     pub clear_env: bool,
+    pub cwd: Option<PathBuf>,
+    /// The configuration file that defined the test.
+    pub config_file: Option<PathBuf>,
+    pub timeout: Option<Duration>,
+    pub timeout_grace: Duration,
+    /// The assertions on the stdout and the stderr of the test.
+    pub expectations: Vec<Expectation>,
+    pub exit_codes: ExitCodes,
+    /// The tags used to select the tests to run.
+    pub tags: Vec<String>,
+    pub stdin: Stdin,
+    /// The command run before the test, in the same directory and with the same environment. The
+    /// test is not run if it fails. Empty if there is none.
+    pub before: Vec<T1>,
+    /// The command run after the test, even if the test or the command before it failed.
+    pub after: Vec<T1>,
+    /// How many times the test is run again if it fails or times out.
+    pub retries: usize,
+    /// The time to wait before running the test again.
+    pub retry_delay: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+    TimedOut,
+    /// The test exited with its skip exit code.
+    Skipped,
+    /// The test exited with its error exit code: it couldn't be run correctly.
+    Error,
+    /// The test was successful, but only after failing at least once.
+    Flaky,
+}
+
+impl Outcome {
+    /// Whether the test failed, timed out or had an error.
+    pub fn is_failure(self) -> bool {
+        match self {
+            Outcome::Success | Outcome::Skipped | Outcome::Flaky => false,
+            Outcome::Failure | Outcome::TimedOut | Outcome::Error => true,
+        }
+    }
+}
+
+/// How the exit code of a test is interpreted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExitCodes {
+    /// The exit codes of a successful test.
+    pub expected: Vec<i32>,
+    /// The exit code of a skipped test, like 77 with automake.
+    pub skip: Option<i32>,
+    /// The exit code of a test that had a hard error, like 99 with automake.
+    pub error: Option<i32>,
+}
+
+impl Default for ExitCodes {
+    fn default() -> ExitCodes {
+        ExitCodes {
+            expected: vec![0],
+            skip: None,
+            error: None,
+        }
+    }
+}
+
+/// What the test reads from its stdin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stdin {
+    Inherit,
+    Null,
+    Text(String),
+    File(PathBuf),
+}
+
+/// What to do with the stdout and stderr of a test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMode {
+    Inherit,
+    /// Capture stdout and stderr together, in the order they were written.
+    Capture,
+    Discard,
+}
+
+/// The result of running a test. `output` is only filled if the output was captured.
+/// `timed_out` contains the timeout if the test was killed because of it. `mismatches`
+/// describes the expectations on the output that were not met. `exit_codes` are the ones of the
+/// test, used to know its outcome. `attempts` is the number of times the test was run, and
+/// `output` and `duration` include every attempt. `before_failure` and `after_failure` describe
+/// the failure of the commands run before and after the test.
+pub struct TestRun {
+    pub status: io::Result<ExitStatus>,
+    pub timed_out: Option<Duration>,
+    pub output: Vec<u8>,
+    pub mismatches: Vec<String>,
+    pub exit_codes: ExitCodes,
+    pub duration: Duration,
+    pub attempts: usize,
+    pub before_failure: Option<String>,
+    pub after_failure: Option<String>,
 }
 
 impl<T1, T2, T3> Test<T1, T2, T3> {
@@ -22,15 +137,47 @@ impl<T1, T2, T3> Test<T1, T2, T3> {
         Test {
             name: name.into(),
             command,
+            shell: None,
             clear_env,
             env,
+            cwd: None,
+            config_file: None,
+            timeout: None,
+            timeout_grace: DEFAULT_TIMEOUT_GRACE,
+            expectations: vec![],
+            exit_codes: Default::default(),
+            tags: vec![],
+            stdin: Stdin::Inherit,
+            before: vec![],
+            after: vec![],
+            retries: 0,
+            retry_delay: Duration::from_secs(0),
+        }
+    }
+}
+
+impl<T1, T2, T3> Test<T1, T2, T3> {
+    /// The working directory of the test relative to the directory of its configuration file, if
+    /// it is another directory.
+    pub fn relative_cwd(&self) -> Option<&Path> {
+        let cwd = self.cwd.as_ref()?;
+        let config_dir = config_dir(self.config_file.as_ref()?);
+
+        match cwd.strip_prefix(config_dir) {
+            Ok(relative) if relative.as_os_str().is_empty() => None,
+            Ok(relative) => Some(relative),
+            Err(_) => Some(cwd),
         }
     }
 }
 
 impl<T1: AsRef<OsStr>, T2: AsRef<OsStr>, T3: AsRef<OsStr>> fmt::Display for Test<T1, T2, T3> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for &(ref name, ref value) in &self.env[..] {
+        if let Some(cwd) = self.relative_cwd() {
+            write!(f, "cd {} && ", escape(cwd.to_string_lossy()))?;
+        }
+
+        for (name, value) in &self.env[..] {
             write!(
                 f,
                 "{}={} ",
@@ -39,55 +186,788 @@ impl<T1: AsRef<OsStr>, T2: AsRef<OsStr>, T3: AsRef<OsStr>> fmt::Display for Test
             )?;
         }
 
-        write!(f, "{}", escape(self.command[0].as_ref().to_string_lossy()))?;
-        for arg in &self.command[1..] {
-            write!(f, " {}", escape(arg.as_ref().to_string_lossy()))?;
+        let shell = self.shell.iter().flat_map(|shell| shell.iter()).map(OsStr::new);
+        let command = self.command.iter().map(AsRef::as_ref);
+        for (i, arg) in shell.chain(command).enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", escape(arg.to_string_lossy()))?;
         }
 
-        Ok(())
+        match self.stdin {
+            Stdin::Text(ref text) => write!(f, " <<< {}", escape(text.as_str().into())),
+            Stdin::File(ref path) => write!(f, " < {}", escape(path.to_string_lossy())),
+            Stdin::Inherit | Stdin::Null => Ok(()),
+        }
     }
 }
 
 impl<T1: AsRef<OsStr>, T2: AsRef<OsStr>, T3: AsRef<OsStr>> Test<T1, T2, T3> {
-    fn run_command(&self) -> io::Result<ExitStatus> {
-        let mut command = Command::new(&self.command[0]);
-        command.args(&self.command[1..]);
+    fn build_command(&self) -> Command {
+        let mut command = match self.shell {
+            Some(ref shell) => {
+                let mut command = Command::new(&shell[0]);
+                command.args(&shell[1..]).args(&self.command);
+                command
+            }
+            None => {
+                let mut command = Command::new(&self.command[0]);
+                command.args(&self.command[1..]);
+                command
+            }
+        };
+
+        if let Some(ref cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
 
         if self.clear_env {
             command.env_clear();
         }
 
-        for &(ref key, ref value) in &self.env {
+        for (key, value) in &self.env {
             command.env(key, value);
         }
 
-        command.status()
+        command
     }
 
-    pub fn run(&self) -> bool {
-        eprintln_bold!("Running test {} ({})", self.name, self);
+    /// Redirect the stdin of the command. With `Stdin::Text`, the text must then be written to
+    /// the stdin of the child.
+    fn redirect_stdin(&self, command: &mut Command) -> io::Result<()> {
+        match self.stdin {
+            Stdin::Inherit => {}
+            Stdin::Null => {
+                command.stdin(Stdio::null());
+            }
+            Stdin::Text(_) => {
+                command.stdin(Stdio::piped());
+            }
+            Stdin::File(ref path) => {
+                let file = File::open(path).map_err(|error| {
+                    io::Error::new(
+                        error.kind(),
+                        format!("cannot open {}: {}", path.display(), error),
+                    )
+                })?;
+                command.stdin(file);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start the test as a background service, in its own process group, without waiting for
+    /// it. Its stdout and stderr are appended to `log` as soon as they are read. It has an empty
+    /// stdin unless it has another one than the inherited one.
+    pub fn spawn_service(&self, log: &Arc<Mutex<Vec<u8>>>) -> io::Result<Child> {
+        let mut command = self.build_command();
+        new_process_group(&mut command);
+
+        self.redirect_stdin(&mut command)?;
+        if let Stdin::Inherit = self.stdin {
+            command.stdin(Stdio::null());
+        }
 
-        let command_result = self.run_command();
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = command.spawn()?;
+
+        if let Stdin::Text(ref text) = self.stdin {
+            write_in_thread(child.stdin.take().unwrap(), text.clone());
+        }
+        read_in_thread(child.stdout.take().unwrap(), vec![log.clone()], None);
+        read_in_thread(child.stderr.take().unwrap(), vec![log.clone()], None);
+
+        Ok(child)
+    }
+
+    /// Run the test. With `OutputMode::Capture`, the stdout and stderr of the test are kept in
+    /// the returned `TestRun`.
+    ///
+    /// If the test has expectations on its output, stdout and stderr are always captured to check
+    /// them, and with `OutputMode::Inherit` the output is printed once the test is finished.
+    ///
+    /// If the test has a timeout and doesn't finish in time, its process group is sent SIGTERM,
+    /// and then SIGKILL if it is still running after the grace period. If it is `interruptible`,
+    /// it is stopped the same way when Ctrl-C is pressed, otherwise it is in its own process group
+    /// and doesn't receive SIGINT, so that it can finish. In its own process group, it can't read
+    /// the terminal, so an inherited stdin is replaced by an empty one.
+    pub fn run_command(&self, output_mode: OutputMode, interruptible: bool) -> TestRun {
+        let start = Instant::now();
+        let mut command = self.build_command();
+
+        let own_process_group = self.timeout.is_some() || !interruptible;
+        if own_process_group {
+            new_process_group(&mut command);
+        }
+
+        let check_output = !self.expectations.is_empty();
+
+        let spawned = self.redirect_stdin(&mut command).and_then(|()| {
+            // A background process group is stopped by SIGTTIN when it reads the terminal.
+            if own_process_group && self.stdin == Stdin::Inherit {
+                command.stdin(Stdio::null());
+            }
+
+            match output_mode {
+                _ if check_output => {
+                    spawn_separated(&mut command).map(|(child, reader)| (child, Some(reader)))
+                }
+                OutputMode::Inherit => command.spawn().map(|child| (child, None)),
+                OutputMode::Capture => {
+                    spawn_captured(&mut command).map(|(child, reader)| (child, Some(reader)))
+                }
+                OutputMode::Discard => command
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .map(|child| (child, None)),
+            }
+        });
 
-        match command_result {
+        let (mut child, output_reader) = match spawned {
+            Ok(spawned) => spawned,
             Err(error) => {
-                eprintln_red!("Test {} failed: {}", self.name, error);
-                false
+                return TestRun {
+                    status: Err(error),
+                    timed_out: None,
+                    output: vec![],
+                    mismatches: vec![],
+                    exit_codes: self.exit_codes.clone(),
+                    duration: start.elapsed(),
+                    attempts: 1,
+                    before_failure: None,
+                    after_failure: None,
+                }
             }
-            Ok(status) => if status.success() {
-                eprintln_green!("Test {} was successful", self.name);
-                true
-            } else {
-                match status.code() {
-                    Some(code) => {
-                        eprintln_red!("Test {} failed: exit code {}", self.name, code);
-                    }
-                    None => {
-                        eprintln_red!("Test {} failed: no exit code", self.name);
+        };
+
+        if own_process_group {
+            register_process_group(child.id());
+        }
+
+        if let Stdin::Text(ref text) = self.stdin {
+            write_in_thread(child.stdin.take().unwrap(), text.clone());
+        }
+
+        let mut timed_out = None;
+        let status = match self.timeout {
+            Some(timeout) => match wait_timeout(&mut child, timeout, interruptible) {
+                Ok(Some(status)) => Ok(status),
+                Ok(None) => {
+                    if !interrupted() {
+                        timed_out = Some(timeout);
                     }
+                    terminate(&mut child, self.timeout_grace)
                 }
-                false
+                Err(error) => Err(error),
             },
+            None => child.wait(),
+        };
+        if own_process_group {
+            unregister_process_group(child.id());
+        }
+
+        let (mut output, streams) = output_reader
+            .map(OutputReader::finish)
+            .unwrap_or_default();
+
+        let mismatches = match streams {
+            Some(ref streams) if status.is_ok() && timed_out.is_none() => self.expectations
+                .iter()
+                .filter_map(|expectation| expectation.check(streams))
+                .collect(),
+            _ => vec![],
+        };
+
+        if check_output {
+            match output_mode {
+                OutputMode::Inherit => {
+                    write_output(&output);
+                    output.clear();
+                }
+                OutputMode::Capture => {}
+                OutputMode::Discard => output.clear(),
+            }
+        }
+
+        TestRun {
+            status,
+            timed_out,
+            output,
+            mismatches,
+            exit_codes: self.exit_codes.clone(),
+            duration: start.elapsed(),
+            attempts: 1,
+            before_failure: None,
+            after_failure: None,
+        }
+    }
+
+    /// A test running one of the commands run before or after this test.
+    fn hook<'a>(&'a self, command: &'a [T1]) -> Test<&'a T1, &'a T2, &'a T3> {
+        let env = self.env
+            .iter()
+            .map(|(name, value)| (name, value))
+            .collect();
+
+        let mut hook = Test::new(self.name.clone(), command.iter().collect(), self.clear_env, env);
+        hook.cwd = self.cwd.clone();
+        hook.timeout = self.timeout;
+        hook.timeout_grace = self.timeout_grace;
+        hook.stdin = Stdin::Null;
+        hook
+    }
+
+    /// Run the command before the test, the test and the command after it. The output of the
+    /// three commands is kept together. The test is not run if the command before it failed, but
+    /// the command after the test is run even then, or if Ctrl-C was pressed.
+    pub fn run(&self, output_mode: OutputMode) -> TestRun {
+        let mut output = vec![];
+        let mut duration = Duration::from_secs(0);
+        let mut before_failed = None;
+
+        if !self.before.is_empty() {
+            let before = self.hook(&self.before).run_command(output_mode, true);
+            if before.outcome() == Outcome::Success {
+                output = before.output;
+                duration = before.duration;
+            } else {
+                before_failed = Some(TestRun {
+                    before_failure: Some(format!(
+                        "the command before the test failed: {}",
+                        before.failure_message().unwrap_or_default()
+                    )),
+                    ..before
+                });
+            }
+        }
+
+        let mut test_run = before_failed.unwrap_or_else(|| self.run_command(output_mode, true));
+
+        if !self.after.is_empty() {
+            let mut after = self.hook(&self.after).run_command(output_mode, false);
+            if after.outcome() != Outcome::Success {
+                test_run.after_failure = Some(format!(
+                    "the command after the test failed: {}",
+                    after.failure_message().unwrap_or_default()
+                ));
+            }
+
+            test_run.output.append(&mut after.output);
+            test_run.duration += after.duration;
         }
+
+        output.append(&mut test_run.output);
+        test_run.output = output;
+        test_run.duration += duration;
+        test_run
+    }
+
+    /// Run the test, and run it again while it fails or times out, up to `retries` times. The
+    /// output of every attempt is kept.
+    pub fn run_with_retries(&self, output_mode: OutputMode) -> TestRun {
+        let mut test_run = self.run(output_mode);
+
+        while test_run.attempts <= self.retries && !interrupted() {
+            match test_run.outcome() {
+                Outcome::Failure | Outcome::TimedOut => {}
+                _ => break,
+            }
+
+            thread::sleep(self.retry_delay);
+
+            let mut retry = self.run(output_mode);
+            test_run.output.append(&mut retry.output);
+            retry.output = test_run.output;
+            retry.duration += test_run.duration;
+            retry.attempts = test_run.attempts + 1;
+            test_run = retry;
+        }
+
+        test_run
+    }
+}
+
+/// The output of a test, and its stdout and stderr if they were captured separately.
+type CapturedOutput = (Vec<u8>, Option<Streams>);
+
+type Buffer = Arc<Mutex<Vec<u8>>>;
+
+/// How long the output of a test is still read once it exited. The processes it started in the
+/// background can keep its output open, and the test would otherwise only finish with them.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The output of a test, read in other threads.
+struct OutputReader {
+    output: Buffer,
+    /// The stdout and stderr of the test, if they are read separately.
+    streams: Option<(Buffer, Buffer)>,
+    /// Receives a message when a thread reached the end of its output.
+    finished: Receiver<()>,
+    readers: usize,
+}
+
+impl OutputReader {
+    /// Wait until the output is read to its end, or at most for `OUTPUT_DRAIN_TIMEOUT`. What is
+    /// written after that is not part of the output.
+    fn finish(self) -> CapturedOutput {
+        let deadline = Instant::now() + OUTPUT_DRAIN_TIMEOUT;
+
+        for _ in 0..self.readers {
+            let now = Instant::now();
+            if now >= deadline || self.finished.recv_timeout(deadline - now).is_err() {
+                break;
+            }
+        }
+
+        // The threads still reading keep their buffers, so they are copied.
+        let contents = |buffer: &Buffer| buffer.lock().unwrap().clone();
+        let streams = self.streams.as_ref().map(|(stdout, stderr)| Streams {
+            stdout: contents(stdout),
+            stderr: contents(stderr),
+        });
+
+        (contents(&self.output), streams)
+    }
+}
+
+/// Spawn the command with its stdout and stderr redirected to the same pipe, and read this pipe
+/// in another thread.
+#[cfg(unix)]
+fn spawn_captured(command: &mut Command) -> io::Result<(Child, OutputReader)> {
+    let (reader, writer) = pipe()?;
+    command.stdout(writer.try_clone()?).stderr(writer);
+    let child = command.spawn();
+
+    // The command keeps the write end of the pipe open until its stdio is replaced. The reader
+    // would otherwise never see the end of the output.
+    command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    let child = child?;
+
+    let output = Arc::new(Mutex::new(vec![]));
+    let (sender, finished) = channel();
+    read_in_thread(reader, vec![output.clone()], Some(sender));
+
+    let reader = OutputReader {
+        output,
+        streams: None,
+        finished,
+        readers: 1,
+    };
+
+    Ok((child, reader))
+}
+
+/// Without a pipe shared by stdout and stderr, stdout and stderr are interleaved in the order
+/// their chunks are read, which may not be the order they were written.
+#[cfg(not(unix))]
+fn spawn_captured(command: &mut Command) -> io::Result<(Child, OutputReader)> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+
+    let output = Arc::new(Mutex::new(vec![]));
+    let (sender, finished) = channel();
+    read_in_thread(
+        child.stdout.take().unwrap(),
+        vec![output.clone()],
+        Some(sender.clone()),
+    );
+    read_in_thread(
+        child.stderr.take().unwrap(),
+        vec![output.clone()],
+        Some(sender),
+    );
+
+    let reader = OutputReader {
+        output,
+        streams: None,
+        finished,
+        readers: 2,
+    };
+
+    Ok((child, reader))
+}
+
+/// Spawn the command with its stdout and stderr redirected to different pipes, so that they can
+/// be checked separately. The combined output is kept too, in the order the chunks were read.
+fn spawn_separated(command: &mut Command) -> io::Result<(Child, OutputReader)> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+
+    let output = Arc::new(Mutex::new(vec![]));
+    let stdout = Arc::new(Mutex::new(vec![]));
+    let stderr = Arc::new(Mutex::new(vec![]));
+    let (sender, finished) = channel();
+    read_in_thread(
+        child.stdout.take().unwrap(),
+        vec![output.clone(), stdout.clone()],
+        Some(sender.clone()),
+    );
+    read_in_thread(
+        child.stderr.take().unwrap(),
+        vec![output.clone(), stderr.clone()],
+        Some(sender),
+    );
+
+    let reader = OutputReader {
+        output,
+        streams: Some((stdout, stderr)),
+        finished,
+        readers: 2,
+    };
+
+    Ok((child, reader))
+}
+
+/// Create a pipe whose ends are not inherited by the other tests running concurrently.
+#[cfg(unix)]
+fn pipe() -> io::Result<(File, File)> {
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+
+    unsafe {
+        if cloexec_pipe(&mut fds) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn cloexec_pipe(fds: &mut [libc::c_int; 2]) -> libc::c_int {
+    libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC)
+}
+
+// There is no pipe2 on this platform, a test spawned concurrently between the creation of the
+// pipe and the call to fcntl can inherit it.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+unsafe fn cloexec_pipe(fds: &mut [libc::c_int; 2]) -> libc::c_int {
+    if libc::pipe(fds.as_mut_ptr()) != 0 {
+        return -1;
+    }
+
+    for &fd in fds.iter() {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+
+    0
+}
+
+/// Write the text in another thread, so that the test can write its output while it reads its
+/// input. The pipe is closed once everything is written, or if the test doesn't read it.
+fn write_in_thread<W: Write + Send + 'static>(mut writer: W, text: String) {
+    thread::spawn(move || {
+        let _ = writer.write_all(text.as_bytes());
+    });
+}
+
+/// Read everything in another thread, copying each chunk to every buffer as soon as it is read.
+/// A message is sent to `finished` once the end of the output is reached.
+fn read_in_thread<R: Read + Send + 'static>(
+    mut reader: R,
+    buffers: Vec<Buffer>,
+    finished: Option<Sender<()>>,
+) {
+    thread::spawn(move || {
+        let mut chunk = [0; 8192];
+
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => for buffer in &buffers {
+                    buffer.lock().unwrap().extend_from_slice(&chunk[..read]);
+                },
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+
+        if let Some(finished) = finished {
+            let _ = finished.send(());
+        }
+    });
+}
+
+/// Wait for the child to exit. Return `None` if it is still running after `timeout`, or if
+/// Ctrl-C was pressed and it is `interruptible`: the child is in its own process group and didn't
+/// receive SIGINT.
+fn wait_timeout(
+    child: &mut Child,
+    timeout: Duration,
+    interruptible: bool,
+) -> io::Result<Option<ExitStatus>> {
+    let start = Instant::now();
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        if start.elapsed() >= timeout || (interruptible && interrupted()) {
+            return Ok(None);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(unix)]
+fn new_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    // This put the test in its own process group, so that it can be killed with all its
+    // children.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn new_process_group(_command: &mut Command) {}
+
+/// Send SIGTERM to the process group of the child, and then SIGKILL if it is still running after
+/// the grace period.
+#[cfg(unix)]
+pub fn terminate(child: &mut Child, grace: Duration) -> io::Result<ExitStatus> {
+    let process_group = -(child.id() as libc::pid_t);
+
+    unsafe {
+        libc::kill(process_group, libc::SIGTERM);
+    }
+
+    let status = wait_timeout(child, grace, false)?;
+
+    // The other processes of the group are killed even if the test itself exited in time,
+    // otherwise they could keep its output pipes open.
+    unsafe {
+        libc::kill(process_group, libc::SIGKILL);
+    }
+
+    match status {
+        Some(status) => Ok(status),
+        None => child.wait(),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn terminate(child: &mut Child, _grace: Duration) -> io::Result<ExitStatus> {
+    child.kill()?;
+    child.wait()
+}
+
+impl TestRun {
+    /// The outcome of the test. It had an error if the command before it failed, or if the
+    /// command after it failed while the test itself didn't fail.
+    pub fn outcome(&self) -> Outcome {
+        let outcome = self.command_outcome();
+
+        match (&self.before_failure, &self.after_failure) {
+            (&Some(_), _) => Outcome::Error,
+            (&None, &Some(_)) if !outcome.is_failure() => Outcome::Error,
+            _ => outcome,
+        }
+    }
+
+    fn command_outcome(&self) -> Outcome {
+        if self.timed_out.is_some() {
+            return Outcome::TimedOut;
+        }
+
+        let exit_code = match self.status {
+            Ok(status) => status.code(),
+            Err(_) => return Outcome::Failure,
+        };
+
+        match exit_code {
+            Some(code) if Some(code) == self.exit_codes.skip => Outcome::Skipped,
+            Some(code) if Some(code) == self.exit_codes.error => Outcome::Error,
+            Some(code) if self.exit_codes.expected.contains(&code) && self.mismatches.is_empty() => {
+                if self.attempts > 1 {
+                    Outcome::Flaky
+                } else {
+                    Outcome::Success
+                }
+            }
+            _ => Outcome::Failure,
+        }
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.status.as_ref().ok().and_then(ExitStatus::code)
+    }
+
+    /// The signal that terminated the test, if any.
+    #[cfg(unix)]
+    pub fn signal(&self) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt;
+
+        self.status.as_ref().ok().and_then(ExitStatusExt::signal)
+    }
+
+    #[cfg(not(unix))]
+    pub fn signal(&self) -> Option<i32> {
+        None
+    }
+
+    /// Describe why the test didn't succeed.
+    pub fn failure_message(&self) -> Option<String> {
+        let failure_message = match self.before_failure {
+            Some(ref before_failure) => Some(before_failure.clone()),
+            None => self.command_failure_message(),
+        };
+
+        let messages: Vec<_> = failure_message
+            .into_iter()
+            .chain(self.after_failure.clone())
+            .collect();
+
+        if messages.is_empty() {
+            None
+        } else {
+            Some(messages.join("\n"))
+        }
+    }
+
+    fn command_failure_message(&self) -> Option<String> {
+        if let Some(timeout) = self.timed_out {
+            return Some(format!("timed out after {}", format_duration(timeout)));
+        }
+
+        let status_message = match self.status {
+            Err(ref error) => Some(error.to_string()),
+            Ok(status) => match status.code() {
+                Some(code) if Some(code) == self.exit_codes.skip => {
+                    return Some(format!("skipped with exit code {}", code))
+                }
+                Some(code) if Some(code) == self.exit_codes.error => {
+                    return Some(format!("exit code {}", code))
+                }
+                Some(code) if self.exit_codes.expected.contains(&code) => None,
+                Some(code) if self.exit_codes.expected == [0] => {
+                    Some(format!("exit code {}", code))
+                }
+                Some(code) => Some(format!(
+                    "exit code {}, expected {}",
+                    code,
+                    self.exit_codes
+                        .expected
+                        .iter()
+                        .map(|code| code.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                )),
+                None => Some("no exit code".to_string()),
+            },
+        };
+
+        let messages: Vec<_> = status_message
+            .into_iter()
+            .chain(self.mismatches.iter().cloned())
+            .collect();
+
+        if messages.is_empty() {
+            None
+        } else {
+            Some(messages.join("\n"))
+        }
+    }
+
+    /// Print the result of the test, preceded by the captured output if `print_output` is true.
+    pub fn report(&self, name: &str, print_output: bool) -> Outcome {
+        if print_output && !self.output.is_empty() {
+            eprintln_bold!("Output of test {}:", name);
+            write_output(&self.output);
+        }
+
+        let outcome = self.outcome();
+
+        match outcome {
+            Outcome::Success => eprintln_green!("Test {} was successful", name),
+            Outcome::Flaky => eprintln_bold!(
+                "Test {} is flaky: it was successful after {} attempts",
+                name,
+                self.attempts
+            ),
+            Outcome::Failure => eprintln_red!(
+                "Test {} failed: {}",
+                name,
+                self.failure_message().unwrap_or_default()
+            ),
+            Outcome::TimedOut => eprintln_red!(
+                "Test {} {}",
+                name,
+                self.failure_message().unwrap_or_default()
+            ),
+            Outcome::Skipped => eprintln_bold!(
+                "Test {} {}",
+                name,
+                self.failure_message().unwrap_or_default()
+            ),
+            Outcome::Error => eprintln_red!(
+                "Test {} had an error: {}",
+                name,
+                self.failure_message().unwrap_or_default()
+            ),
+        }
+
+        outcome
+    }
+}
+
+/// Write the captured output of a test to stdout.
+pub fn write_output(output: &[u8]) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = stdout.write_all(output);
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(script: &str) -> Vec<String> {
+        vec!["sh".to_string(), "-c".to_string(), script.to_string()]
+    }
+
+    #[test]
+    fn runs_the_command_after_a_failed_command_before() {
+        let mut test =
+            Test::<String, String, String>::new("test", command("echo test"), false, vec![]);
+        test.before = command("echo before; exit 1");
+        test.after = command("echo after");
+
+        let test_run = test.run(OutputMode::Capture);
+        assert_eq!(String::from_utf8_lossy(&test_run.output), "before\nafter\n");
+        assert_eq!(test_run.outcome(), Outcome::Error);
+        assert_eq!(
+            test_run.failure_message().unwrap(),
+            "the command before the test failed: exit code 1"
+        );
+    }
+
+    #[test]
+    fn reports_the_failures_of_the_commands_before_and_after() {
+        let mut test = Test::<String, String, String>::new("test", command("true"), false, vec![]);
+        test.before = command("exit 1");
+        test.after = command("exit 2");
+
+        let test_run = test.run(OutputMode::Capture);
+        assert_eq!(test_run.outcome(), Outcome::Error);
+        assert_eq!(
+            test_run.failure_message().unwrap(),
+            "the command before the test failed: exit code 1\n\
+             the command after the test failed: exit code 2"
+        );
     }
 }